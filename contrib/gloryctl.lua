@@ -0,0 +1,284 @@
+-- Wireshark dissector for the configuration protocol of Glorious mice.
+-- Generated by `gloryctl dissector` from src/protocol/layout.rs, do not edit.
+-- Copy it to ~/.local/lib/wireshark/plugins/ to use it.
+
+local proto = Proto("gloryctl", "Glorious mouse configuration")
+
+local report_msg = 5
+local report_data = 4
+local cmd_version = 0x01
+local macro_count_offset = 10
+
+local commands = {
+    [0x01] = "version",
+    [0x11] = "config",
+    [0x12] = "button map",
+    [0x1a] = "debounce",
+    [0x30] = "macro",
+}
+local effects = {
+    [0x00] = "Off",
+    [0x01] = "Glorious",
+    [0x02] = "SingleColor",
+    [0x03] = "Breathing",
+    [0x04] = "Tail",
+    [0x05] = "SeamlessBreathing",
+    [0x06] = "ConstantRgb",
+    [0x07] = "Rave",
+    [0x08] = "Random",
+    [0x09] = "Wave",
+    [0x0a] = "SingleBreathing",
+}
+local button_actions = {
+    [0x11] = "mouse",
+    [0x12] = "scroll",
+    [0x21] = "keyboard",
+    [0x22] = "media",
+    [0x31] = "repeat",
+    [0x41] = "dpi",
+    [0x42] = "dpi-lock",
+    [0x50] = "disable",
+    [0x70] = "macro",
+}
+local event_types = {
+    [0x01] = "mouse",
+    [0x05] = "keyboard",
+    [0x06] = "modifier",
+}
+local event_states = {
+    [0x00] = "down",
+    [0x01] = "up",
+}
+
+local f = {}
+f["report_id"] = ProtoField.uint8("gloryctl.report_id", "Report ID")
+f["command"] = ProtoField.uint8("gloryctl.command", "Command", base.HEX, commands)
+f["argument"] = ProtoField.uint8("gloryctl.argument", "Argument", base.HEX)
+f["dpi"] = ProtoField.uint8("gloryctl.dpi", "DPI value")
+f["color"] = ProtoField.bytes("gloryctl.color", "Color")
+f["brightness"] = ProtoField.uint8("gloryctl.brightness", "Brightness", base.DEC, nil, 0xf0)
+f["speed"] = ProtoField.uint8("gloryctl.speed", "Speed", base.DEC, nil, 0x0f)
+f["action.type"] = ProtoField.uint8("gloryctl.action.type", "Action", base.HEX, button_actions)
+f["action.params"] = ProtoField.bytes("gloryctl.action.params", "Parameters")
+f["event.state"] = ProtoField.uint8("gloryctl.event.state", "State", base.DEC, event_states, 0x80)
+f["event.type"] = ProtoField.uint8("gloryctl.event.type", "Type", base.DEC, event_types, 0x70)
+f["event.duration"] = ProtoField.uint16("gloryctl.event.duration", "Duration", base.DEC, nil, 0x0fff)
+f["event.key"] = ProtoField.uint8("gloryctl.event.key", "Key", base.HEX)
+f["config.header"] = ProtoField.bytes("gloryctl.config.header", "header")
+f["config.sensor_id"] = ProtoField.uint8("gloryctl.config.sensor_id", "sensor_id")
+f["config.dpi_axes_independent"] = ProtoField.uint8("gloryctl.config.dpi_axes_independent", "dpi_axes_independent", base.DEC, nil, 0xf0)
+f["config.polling_rate"] = ProtoField.uint8("gloryctl.config.polling_rate", "polling_rate", base.DEC, nil, 0x0f)
+f["config.dpi_current_profile"] = ProtoField.uint8("gloryctl.config.dpi_current_profile", "dpi_current_profile", base.DEC, nil, 0xf0)
+f["config.dpi_profile_count"] = ProtoField.uint8("gloryctl.config.dpi_profile_count", "dpi_profile_count", base.DEC, nil, 0x0f)
+f["config.dpi_enabled_mask"] = ProtoField.uint8("gloryctl.config.dpi_enabled_mask", "dpi_enabled_mask", base.HEX)
+f["config.dpi_values"] = ProtoField.bytes("gloryctl.config.dpi_values", "dpi_values")
+f["config.dpi_colors"] = ProtoField.bytes("gloryctl.config.dpi_colors", "dpi_colors")
+f["config.rgb_current_effect"] = ProtoField.uint8("gloryctl.config.rgb_current_effect", "rgb_current_effect", base.DEC, effects)
+f["config.glorious.bs"] = ProtoField.uint8("gloryctl.config.glorious.bs", "glorious.bs", base.HEX)
+f["config.glorious.direction"] = ProtoField.uint8("gloryctl.config.glorious.direction", "glorious.direction")
+f["config.single_color.bs"] = ProtoField.uint8("gloryctl.config.single_color.bs", "single_color.bs", base.HEX)
+f["config.single_color.color"] = ProtoField.bytes("gloryctl.config.single_color.color", "single_color.color")
+f["config.breathing.bs"] = ProtoField.uint8("gloryctl.config.breathing.bs", "breathing.bs", base.HEX)
+f["config.breathing.count"] = ProtoField.uint8("gloryctl.config.breathing.count", "breathing.count")
+f["config.breathing.colors"] = ProtoField.bytes("gloryctl.config.breathing.colors", "breathing.colors")
+f["config.tail.bs"] = ProtoField.uint8("gloryctl.config.tail.bs", "tail.bs", base.HEX)
+f["config.seamless_breathing.bs"] = ProtoField.uint8("gloryctl.config.seamless_breathing.bs", "seamless_breathing.bs", base.HEX)
+f["config.constant_rgb.bs"] = ProtoField.uint8("gloryctl.config.constant_rgb.bs", "constant_rgb.bs", base.HEX)
+f["config.constant_rgb.colors"] = ProtoField.bytes("gloryctl.config.constant_rgb.colors", "constant_rgb.colors")
+f["config.unknown"] = ProtoField.bytes("gloryctl.config.unknown", "unknown")
+f["config.rave.bs"] = ProtoField.uint8("gloryctl.config.rave.bs", "rave.bs", base.HEX)
+f["config.rave.colors"] = ProtoField.bytes("gloryctl.config.rave.colors", "rave.colors")
+f["config.random.bs"] = ProtoField.uint8("gloryctl.config.random.bs", "random.bs", base.HEX)
+f["config.wave.bs"] = ProtoField.uint8("gloryctl.config.wave.bs", "wave.bs", base.HEX)
+f["config.single_breathing.bs"] = ProtoField.uint8("gloryctl.config.single_breathing.bs", "single_breathing.bs", base.HEX)
+f["config.single_breathing.color"] = ProtoField.bytes("gloryctl.config.single_breathing.color", "single_breathing.color")
+f["config.lod"] = ProtoField.uint8("gloryctl.config.lod", "lod")
+f["buttonmap.header"] = ProtoField.bytes("gloryctl.buttonmap.header", "header")
+f["buttonmap.button_count"] = ProtoField.uint8("gloryctl.buttonmap.button_count", "button count")
+f["buttonmap.unknown"] = ProtoField.bytes("gloryctl.buttonmap.unknown", "unknown")
+f["buttonmap.button_1"] = ProtoField.bytes("gloryctl.buttonmap.button_1", "button 1")
+f["buttonmap.button_2"] = ProtoField.bytes("gloryctl.buttonmap.button_2", "button 2")
+f["buttonmap.button_3"] = ProtoField.bytes("gloryctl.buttonmap.button_3", "button 3")
+f["buttonmap.button_4"] = ProtoField.bytes("gloryctl.buttonmap.button_4", "button 4")
+f["buttonmap.button_5"] = ProtoField.bytes("gloryctl.buttonmap.button_5", "button 5")
+f["buttonmap.button_6"] = ProtoField.bytes("gloryctl.buttonmap.button_6", "button 6")
+f["buttonmap.button_7"] = ProtoField.bytes("gloryctl.buttonmap.button_7", "button 7")
+f["buttonmap.button_8"] = ProtoField.bytes("gloryctl.buttonmap.button_8", "button 8")
+f["buttonmap.button_9"] = ProtoField.bytes("gloryctl.buttonmap.button_9", "button 9")
+f["buttonmap.button_10"] = ProtoField.bytes("gloryctl.buttonmap.button_10", "button 10")
+f["buttonmap.button_11"] = ProtoField.bytes("gloryctl.buttonmap.button_11", "button 11")
+f["buttonmap.button_12"] = ProtoField.bytes("gloryctl.buttonmap.button_12", "button 12")
+f["buttonmap.button_13"] = ProtoField.bytes("gloryctl.buttonmap.button_13", "button 13")
+f["buttonmap.button_14"] = ProtoField.bytes("gloryctl.buttonmap.button_14", "button 14")
+f["buttonmap.button_15"] = ProtoField.bytes("gloryctl.buttonmap.button_15", "button 15")
+f["buttonmap.button_16"] = ProtoField.bytes("gloryctl.buttonmap.button_16", "button 16")
+f["buttonmap.button_17"] = ProtoField.bytes("gloryctl.buttonmap.button_17", "button 17")
+f["buttonmap.button_18"] = ProtoField.bytes("gloryctl.buttonmap.button_18", "button 18")
+f["buttonmap.button_19"] = ProtoField.bytes("gloryctl.buttonmap.button_19", "button 19")
+f["buttonmap.button_20"] = ProtoField.bytes("gloryctl.buttonmap.button_20", "button 20")
+f["macro.header"] = ProtoField.bytes("gloryctl.macro.header", "header")
+f["macro.bank"] = ProtoField.uint8("gloryctl.macro.bank", "bank")
+f["macro.unknown"] = ProtoField.bytes("gloryctl.macro.unknown", "unknown")
+f["macro.event_count"] = ProtoField.uint8("gloryctl.macro.event_count", "event count")
+f["macro.events"] = ProtoField.bytes("gloryctl.macro.events", "events")
+f["version.header"] = ProtoField.bytes("gloryctl.version.header", "header")
+f["version.version"] = ProtoField.string("gloryctl.version.version", "version")
+
+local fields = {}
+for _, pf in pairs(f) do
+    table.insert(fields, pf)
+end
+proto.fields = fields
+
+local layouts = {}
+layouts["config"] = {
+    { 0, 9, "raw", f["config.header"] },
+    { 9, 1, "number", f["config.sensor_id"] },
+    { 10, 1, "nibbles", f["config.dpi_axes_independent"], f["config.polling_rate"] },
+    { 11, 1, "nibbles", f["config.dpi_current_profile"], f["config.dpi_profile_count"] },
+    { 12, 1, "number", f["config.dpi_enabled_mask"] },
+    { 13, 16, "dpi", f["config.dpi_values"] },
+    { 29, 24, "rgb", f["config.dpi_colors"] },
+    { 53, 1, "number", f["config.rgb_current_effect"] },
+    { 54, 1, "bs", f["config.glorious.bs"] },
+    { 55, 1, "number", f["config.glorious.direction"] },
+    { 56, 1, "bs", f["config.single_color.bs"] },
+    { 57, 3, "rbg", f["config.single_color.color"] },
+    { 60, 1, "bs", f["config.breathing.bs"] },
+    { 61, 1, "number", f["config.breathing.count"] },
+    { 62, 21, "rbg", f["config.breathing.colors"] },
+    { 83, 1, "bs", f["config.tail.bs"] },
+    { 84, 1, "bs", f["config.seamless_breathing.bs"] },
+    { 85, 1, "bs", f["config.constant_rgb.bs"] },
+    { 86, 18, "rbg", f["config.constant_rgb.colors"] },
+    { 104, 12, "raw", f["config.unknown"] },
+    { 116, 1, "bs", f["config.rave.bs"] },
+    { 117, 6, "rbg", f["config.rave.colors"] },
+    { 123, 1, "bs", f["config.random.bs"] },
+    { 124, 1, "bs", f["config.wave.bs"] },
+    { 125, 1, "bs", f["config.single_breathing.bs"] },
+    { 126, 3, "rbg", f["config.single_breathing.color"] },
+    { 129, 1, "number", f["config.lod"] },
+    { 130, 1, "raw", f["config.unknown"] },
+}
+layouts["buttonmap"] = {
+    { 0, 6, "raw", f["buttonmap.header"] },
+    { 6, 1, "number", f["buttonmap.button_count"] },
+    { 7, 1, "raw", f["buttonmap.unknown"] },
+    { 8, 4, "action", f["buttonmap.button_1"] },
+    { 12, 4, "action", f["buttonmap.button_2"] },
+    { 16, 4, "action", f["buttonmap.button_3"] },
+    { 20, 4, "action", f["buttonmap.button_4"] },
+    { 24, 4, "action", f["buttonmap.button_5"] },
+    { 28, 4, "action", f["buttonmap.button_6"] },
+    { 32, 4, "action", f["buttonmap.button_7"] },
+    { 36, 4, "action", f["buttonmap.button_8"] },
+    { 40, 4, "action", f["buttonmap.button_9"] },
+    { 44, 4, "action", f["buttonmap.button_10"] },
+    { 48, 4, "action", f["buttonmap.button_11"] },
+    { 52, 4, "action", f["buttonmap.button_12"] },
+    { 56, 4, "action", f["buttonmap.button_13"] },
+    { 60, 4, "action", f["buttonmap.button_14"] },
+    { 64, 4, "action", f["buttonmap.button_15"] },
+    { 68, 4, "action", f["buttonmap.button_16"] },
+    { 72, 4, "action", f["buttonmap.button_17"] },
+    { 76, 4, "action", f["buttonmap.button_18"] },
+    { 80, 4, "action", f["buttonmap.button_19"] },
+    { 84, 4, "action", f["buttonmap.button_20"] },
+}
+layouts["macro"] = {
+    { 0, 8, "raw", f["macro.header"] },
+    { 8, 1, "number", f["macro.bank"] },
+    { 9, 1, "raw", f["macro.unknown"] },
+    { 10, 1, "number", f["macro.event_count"] },
+    { 11, 504, "events", f["macro.events"] },
+}
+layouts["version"] = {
+    { 0, 2, "raw", f["version.header"] },
+    { 2, 4, "raw", f["version.version"] },
+}
+
+local data_layouts = {
+    [0x11] = layouts["config"],
+    [0x12] = layouts["buttonmap"],
+    [0x30] = layouts["macro"],
+}
+
+local function add_fields(tree, buf, layout)
+    for _, fld in ipairs(layout) do
+        local offset, len, kind, pf = fld[1], fld[2], fld[3], fld[4]
+        if offset + len > buf:len() then
+            break
+        end
+        local range = buf(offset, len)
+        if kind == "nibbles" then
+            tree:add(pf, range)
+            tree:add(fld[5], range)
+        elseif kind == "bs" then
+            local sub = tree:add(pf, range)
+            sub:add(f["brightness"], range)
+            sub:add(f["speed"], range)
+        elseif kind == "dpi" then
+            local sub = tree:add(pf, range)
+            for i = 0, len - 1 do
+                local v = buf(offset + i, 1)
+                sub:add(f["dpi"], v):append_text(string.format(" (%d DPI)", (v:uint() + 1) * 100))
+            end
+        elseif kind == "rgb" or kind == "rbg" then
+            local sub = tree:add(pf, range)
+            for i = 0, len - 3, 3 do
+                local c = buf(offset + i, 3)
+                local r, g, b = c(0, 1):uint(), c(1, 1):uint(), c(2, 1):uint()
+                if kind == "rbg" then
+                    g, b = b, g
+                end
+                sub:add(f["color"], c):append_text(string.format(" (#%02x%02x%02x)", r, g, b))
+            end
+        elseif kind == "action" then
+            local sub = tree:add(pf, range)
+            sub:add(f["action.type"], buf(offset, 1))
+            sub:add(f["action.params"], buf(offset + 1, len - 1))
+        elseif kind == "events" then
+            local sub = tree:add(pf, range)
+            local count = math.min(buf(macro_count_offset, 1):uint(), math.floor(len / 3))
+            for i = 0, count - 1 do
+                local e = buf(offset + 3 * i, 3)
+                local ev = sub:add(e, "Event " .. (i + 1))
+                ev:add(f["event.state"], e(0, 1))
+                ev:add(f["event.type"], e(0, 1))
+                ev:add(f["event.duration"], e(0, 2))
+                ev:add(f["event.key"], e(2, 1))
+            end
+        else
+            tree:add(pf, range)
+        end
+    end
+end
+
+function proto.dissector(buf, pinfo, tree)
+    if buf:len() < 2 then
+        return 0
+    end
+    local id, cmd = buf(0, 1):uint(), buf(1, 1):uint()
+    if id ~= report_msg and id ~= report_data then
+        return 0
+    end
+    pinfo.cols.protocol = proto.name
+    pinfo.cols.info = string.format("Report %d, %s", id,
+        commands[cmd] or string.format("unknown command 0x%02x", cmd))
+    local sub = tree:add(proto, buf())
+    sub:add(f["report_id"], buf(0, 1))
+    sub:add(f["command"], buf(1, 1))
+    if id == report_msg then
+        if cmd == cmd_version and buf:len() >= 6 and buf(2, 1):uint() ~= 0 then
+            add_fields(sub, buf, layouts["version"])
+        elseif buf:len() >= 3 then
+            sub:add(f["argument"], buf(2, 1))
+        end
+    elseif data_layouts[cmd] then
+        add_fields(sub, buf, data_layouts[cmd])
+    end
+    return buf:len()
+end
+
+DissectorTable.get("usb.product"):add(0x258a0036, proto) -- Glorious Model O
@@ -0,0 +1,17 @@
+//! Decodes arbitrary button map reports. A report which decodes has to encode
+//! again, and decode to the same mapping.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use gloryctl::{decode, encode};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(map) = decode::buttonmap(data) {
+        let raw = encode::buttonmap(&map).expect("a decoded mapping encodes");
+        assert_eq!(
+            decode::buttonmap(&raw).expect("an encoded mapping decodes"),
+            map
+        );
+    }
+});
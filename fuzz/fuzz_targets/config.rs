@@ -0,0 +1,17 @@
+//! Decodes arbitrary config reports. A report which decodes has to encode
+//! again, and decode to the same config.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use gloryctl::{decode, Config};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(cfg) = decode::config(data) {
+        let raw = cfg.to_raw().expect("a decoded config encodes");
+        assert_eq!(
+            Config::from_raw(&raw).expect("an encoded config decodes"),
+            cfg
+        );
+    }
+});
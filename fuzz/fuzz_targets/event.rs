@@ -0,0 +1,16 @@
+//! Decodes arbitrary macro events, three octets at a time. An event which
+//! decodes has to encode again to the same octets.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use gloryctl::{decode, encode};
+
+fuzz_target!(|data: &[u8]| {
+    for octets in data.chunks_exact(3) {
+        if let Ok(event) = decode::event(octets) {
+            let raw = encode::macro_bank(0, &[event]).expect("a decoded event encodes");
+            assert_eq!(&raw[11..14], octets);
+        }
+    }
+});
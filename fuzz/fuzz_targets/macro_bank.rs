@@ -0,0 +1,17 @@
+//! Decodes arbitrary macro bank reports, and so their events. A report which
+//! decodes has to encode again, and decode to the same macro.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use gloryctl::{decode, encode};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(m) = decode::macro_bank(data) {
+        let raw = encode::macro_bank(m.bank_number, &m.events).expect("a decoded macro encodes");
+        assert_eq!(
+            decode::macro_bank(&raw).expect("an encoded macro decodes"),
+            m
+        );
+    }
+});
@@ -0,0 +1,96 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hex::FromHex;
+use serde::{Deserialize, Serialize};
+
+use crate::device::{DataReport, GloriousDevice};
+use crate::error::{Error, Result};
+use crate::transport::Transport;
+
+/// Offset of the sensor ID in the config report.
+const SENSOR_ID_OFFSET: usize = 9;
+
+/// A byte-exact copy of the config report, together with information about
+/// the mouse it was taken from. Unlike a profile, it preserves the octets
+/// which are not understood by the decoder.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backup {
+    pub firmware: String,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    #[serde(serialize_with = "report_to_hex", deserialize_with = "report_from_hex")]
+    pub config: DataReport,
+}
+
+fn report_to_hex<S: serde::Serializer>(report: &DataReport, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&hex::encode(&report[..]))
+}
+
+fn report_from_hex<'de, D: serde::Deserializer<'de>>(d: D) -> Result<DataReport, D::Error> {
+    use serde::de::Error;
+
+    let s = String::deserialize(d)?;
+    let bytes = Vec::from_hex(s.trim()).map_err(D::Error::custom)?;
+    <DataReport>::try_from(bytes.as_slice())
+        .map_err(|_| D::Error::custom(format!("expected 520 octets, got {}", bytes.len())))
+}
+
+impl Backup {
+    pub fn read_from<T: Transport>(dev: &GloriousDevice<T>) -> Result<Self> {
+        Ok(Backup {
+            firmware: dev.read_fw_version()?,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            config: dev.read_config_raw()?,
+        })
+    }
+
+    pub fn sensor_id(&self) -> u8 {
+        self.config[SENSOR_ID_OFFSET]
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(Error::io(path))?;
+        toml::from_str(&text).map_err(|e| parse_error!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = toml::to_string(self).map_err(|e| Error::encode("backup", e.to_string()))?;
+        fs::write(path, text).map_err(Error::io(path))
+    }
+
+    /// Write the backed up config report to the mouse. Unless `force` is set,
+    /// this fails if the mouse runs a different firmware version or has a
+    /// different sensor than the one the backup was taken from.
+    pub fn restore_to<T: Transport>(&self, dev: &mut GloriousDevice<T>, force: bool) -> Result<()> {
+        if !force {
+            let firmware = dev.read_fw_version()?;
+            if firmware != self.firmware {
+                return Err(Error::encode(
+                    "firmware",
+                    format!(
+                        "the backup was taken from firmware {}, but the mouse runs {}",
+                        self.firmware, firmware
+                    ),
+                ));
+            }
+            let current = dev.read_config_raw()?;
+            if current[SENSOR_ID_OFFSET] != self.sensor_id() {
+                return Err(Error::encode(
+                    "sensor_id",
+                    format!(
+                        "the backup was taken from a mouse with sensor {:#04x}, but this one has {:#04x}",
+                        self.sensor_id(),
+                        current[SENSOR_ID_OFFSET]
+                    ),
+                ));
+            }
+        }
+        dev.send_config_raw(&self.config)
+    }
+}
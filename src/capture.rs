@@ -0,0 +1,506 @@
+//! Reading usbmon captures of the communication with the mouse, as saved by
+//! Wireshark or tcpdump in the pcap or pcapng format, and turning them into a
+//! decoded transcript. Only the HID feature reports of the control interface
+//! are looked at.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::device::{
+    Config, DataReport, HW_CMD_CONF, HW_CMD_DEBOUNCE, HW_CMD_MACRO, HW_CMD_MAP, HW_CMD_VER,
+    HW_CONF_WRITE_MAGIC, HW_MAP_WRITE_MAGIC, HW_REPORT_DATA, HW_REPORT_MSG,
+};
+use crate::error::{Error, Result};
+use crate::model::Registry;
+use crate::protocol::{decode, layout};
+use crate::verify;
+
+/// usbmon packets with a 48 octet header.
+const LINKTYPE_USB_LINUX: u32 = 189;
+/// usbmon packets with a 64 octet header, as captured using the binary interface.
+const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+
+const URB_CONTROL: u8 = 2;
+const HID_SET_REPORT: (u8, u8) = (0x21, 0x09);
+const HID_GET_REPORT: (u8, u8) = (0xa1, 0x01);
+const HID_FEATURE_REPORT: u8 = 3;
+const GET_DEVICE_DESCRIPTOR: (u8, u8, u16) = (0x80, 0x06, 0x0100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    SetReport,
+    GetReport,
+}
+
+/// A feature report written to or read from the control interface.
+#[derive(Debug, Clone)]
+pub struct ReportTransfer {
+    /// Seconds since the first packet of the capture.
+    pub time: f64,
+    pub request: Request,
+    pub report_id: u8,
+    /// The report, starting with its ID.
+    pub data: Vec<u8>,
+}
+
+/// Reads fixed-size integers from a buffer in the byte order of the capture.
+struct Reader<'a> {
+    buf: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8]> {
+        self.buf
+            .get(offset..offset + len)
+            .ok_or_else(|| parse_error!("Capture truncated at offset {}", offset))
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        let b = self.bytes(offset, 2)?.try_into().unwrap_or_default();
+        Ok(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        let b = self.bytes(offset, 4)?.try_into().unwrap_or_default();
+        Ok(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64> {
+        let b = self.bytes(offset, 8)?.try_into().unwrap_or_default();
+        Ok(if self.big_endian {
+            u64::from_be_bytes(b)
+        } else {
+            u64::from_le_bytes(b)
+        })
+    }
+}
+
+/// A captured packet, still including the usbmon header.
+struct Packet<'a> {
+    linktype: u32,
+    big_endian: bool,
+    /// Seconds since the Unix epoch.
+    time: f64,
+    data: &'a [u8],
+}
+
+fn pcap_packets(file: &[u8]) -> Result<Vec<Packet<'_>>> {
+    let (big_endian, units) = match file.get(..4) {
+        Some([0xd4, 0xc3, 0xb2, 0xa1]) => (false, 1e6),
+        Some([0xa1, 0xb2, 0xc3, 0xd4]) => (true, 1e6),
+        Some([0x4d, 0x3c, 0xb2, 0xa1]) => (false, 1e9),
+        Some([0xa1, 0xb2, 0x3c, 0x4d]) => (true, 1e9),
+        _ => return Err(parse_error!("Not a pcap file")),
+    };
+    let r = Reader {
+        buf: file,
+        big_endian,
+    };
+    let linktype = r.u32(20)?;
+    let mut packets = Vec::new();
+    let mut pos = 24;
+    while pos < file.len() {
+        let len = r.u32(pos + 8)? as usize;
+        packets.push(Packet {
+            linktype,
+            big_endian,
+            time: f64::from(r.u32(pos)?) + f64::from(r.u32(pos + 4)?) / units,
+            data: r.bytes(pos + 16, len)?,
+        });
+        pos += 16 + len;
+    }
+    Ok(packets)
+}
+
+fn pcapng_packets(file: &[u8]) -> Result<Vec<Packet<'_>>> {
+    const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+    const INTERFACE_DESCRIPTION: u32 = 1;
+    const ENHANCED_PACKET: u32 = 6;
+    const IF_TSRESOL: u16 = 9;
+
+    let mut r = Reader {
+        buf: file,
+        big_endian: false,
+    };
+    // (linktype, timestamp units per second) of each interface in the section
+    let mut interfaces: Vec<(u32, f64)> = Vec::new();
+    let mut packets = Vec::new();
+    let mut pos = 0;
+    while pos < file.len() {
+        if r.u32(pos)? == SECTION_HEADER {
+            r.big_endian = match r.bytes(pos + 8, 4)? {
+                [0x4d, 0x3c, 0x2b, 0x1a] => false,
+                [0x1a, 0x2b, 0x3c, 0x4d] => true,
+                _ => return Err(parse_error!("Invalid pcapng section at offset {}", pos)),
+            };
+            interfaces.clear();
+        }
+        let typ = r.u32(pos)?;
+        let len = r.u32(pos + 4)? as usize;
+        if len < 12 {
+            return Err(parse_error!("Invalid pcapng block at offset {}", pos));
+        }
+        let body = pos + 8;
+        match typ {
+            INTERFACE_DESCRIPTION => {
+                let mut units = 1e6;
+                let mut opt = body + 8;
+                while opt + 4 <= pos + len - 4 {
+                    let code = r.u16(opt)?;
+                    let opt_len = r.u16(opt + 2)? as usize;
+                    if code == 0 {
+                        break;
+                    }
+                    if code == IF_TSRESOL && opt_len == 1 {
+                        let res = r.bytes(opt + 4, 1)?[0];
+                        units = if res & 0x80 != 0 {
+                            2f64.powi(i32::from(res & 0x7f))
+                        } else {
+                            10f64.powi(i32::from(res))
+                        };
+                    }
+                    opt += 4 + opt_len.div_ceil(4) * 4;
+                }
+                interfaces.push((u32::from(r.u16(body)?), units));
+            }
+            ENHANCED_PACKET => {
+                let &(linktype, units) = interfaces
+                    .get(r.u32(body)? as usize)
+                    .ok_or_else(|| parse_error!("Unknown interface in packet at offset {}", pos))?;
+                let ts = (u64::from(r.u32(body + 4)?) << 32) | u64::from(r.u32(body + 8)?);
+                packets.push(Packet {
+                    linktype,
+                    big_endian: r.big_endian,
+                    time: ts as f64 / units,
+                    data: r.bytes(body + 20, r.u32(body + 12)? as usize)?,
+                });
+            }
+            _ => {}
+        }
+        pos += len;
+    }
+    Ok(packets)
+}
+
+/// The parts of a usbmon packet needed to follow control transfers.
+struct Urb<'a> {
+    id: u64,
+    submission: bool,
+    bus: u16,
+    device: u8,
+    /// bmRequestType, bRequest, wValue and wIndex of a submitted control transfer.
+    setup: Option<(u8, u8, u16, u16)>,
+    data: &'a [u8],
+}
+
+impl<'a> Urb<'a> {
+    fn parse(packet: &Packet<'a>) -> Result<Option<Self>> {
+        let header_len = match packet.linktype {
+            LINKTYPE_USB_LINUX => 48,
+            LINKTYPE_USB_LINUX_MMAPPED => 64,
+            other => {
+                return Err(parse_error!(
+                    "Unsupported link type {}, expected a usbmon capture",
+                    other
+                ))
+            }
+        };
+        let r = Reader {
+            buf: packet.data,
+            big_endian: packet.big_endian,
+        };
+        let head = r.bytes(8, 4)?;
+        if head[1] != URB_CONTROL {
+            return Ok(None);
+        }
+        let submission = head[0] == b'S';
+        let setup = if submission && r.bytes(14, 1)?[0] == 0 {
+            let s = r.bytes(40, 8)?;
+            Some((
+                s[0],
+                s[1],
+                u16::from_le_bytes([s[2], s[3]]),
+                u16::from_le_bytes([s[4], s[5]]),
+            ))
+        } else {
+            None
+        };
+        Ok(Some(Urb {
+            id: r.u64(0)?,
+            submission,
+            bus: r.u16(12)?,
+            device: head[3],
+            setup,
+            data: packet.data.get(header_len..).unwrap_or(&[]),
+        }))
+    }
+}
+
+/// Extract the feature reports transferred to and from the mouse from a
+/// usbmon capture. If the capture contains the device descriptor of a mouse
+/// in the registry, transfers to other devices are left out.
+pub fn read_usbmon(path: &Path, registry: &Registry) -> Result<Vec<ReportTransfer>> {
+    let file = fs::read(path).map_err(Error::io(path))?;
+    let packets = match file.get(..4) {
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => pcapng_packets(&file),
+        _ => pcap_packets(&file),
+    }
+    .map_err(|e| parse_error!("{}: {}", path.display(), e))?;
+
+    let start = packets.first().map_or(0.0, |p| p.time);
+    let mut pending = HashMap::new();
+    let mut mice = HashSet::new();
+    let mut transfers = Vec::new();
+    for packet in &packets {
+        let urb = match Urb::parse(packet).map_err(|e| parse_error!("{}: {}", path.display(), e))? {
+            Some(urb) => urb,
+            None => continue,
+        };
+        if urb.submission {
+            if let Some(setup) = urb.setup {
+                pending.insert(urb.id, (setup, urb.data));
+            }
+            continue;
+        }
+        let ((request_type, request, value, index), sent) = match pending.remove(&urb.id) {
+            Some(p) => p,
+            None => continue,
+        };
+        if (request_type, request, value) == GET_DEVICE_DESCRIPTOR && urb.data.len() >= 12 {
+            let vendor = u16::from_le_bytes([urb.data[8], urb.data[9]]);
+            let product = u16::from_le_bytes([urb.data[10], urb.data[11]]);
+            if registry.find(vendor, product).is_some() {
+                mice.insert((urb.bus, urb.device));
+            }
+            continue;
+        }
+        let (report_type, report_id) = ((value >> 8) as u8, value as u8);
+        let control = registry
+            .models()
+            .iter()
+            .any(|m| m.control_interface == i32::from(index));
+        if report_type != HID_FEATURE_REPORT || !control {
+            continue;
+        }
+        let (req, data) = match (request_type, request) {
+            HID_SET_REPORT => (Request::SetReport, sent),
+            HID_GET_REPORT => (Request::GetReport, urb.data),
+            _ => continue,
+        };
+        transfers.push((
+            (urb.bus, urb.device),
+            ReportTransfer {
+                time: packet.time - start,
+                request: req,
+                report_id,
+                data: data.to_vec(),
+            },
+        ));
+    }
+    Ok(transfers
+        .into_iter()
+        .filter(|(dev, _)| mice.is_empty() || mice.contains(dev))
+        .map(|(_, t)| t)
+        .collect())
+}
+
+fn command_name(cmd: u8) -> Option<&'static str> {
+    layout::COMMANDS
+        .iter()
+        .find(|(c, _)| *c == cmd)
+        .map(|(_, name)| *name)
+}
+
+fn hex_lines(out: &mut String, fields: &[layout::Field], data: &[u8]) {
+    for line in layout::annotate(fields, data).lines() {
+        let _ = writeln!(out, "    {}", line);
+    }
+}
+
+fn describe_config(out: &mut String, data: &[u8]) {
+    let mut report: DataReport = [0; 520];
+    let len = data.len().min(report.len());
+    report[..len].copy_from_slice(&data[..len]);
+    let conf = match Config::from_raw(&report) {
+        Ok(conf) => conf,
+        Err(e) => {
+            let _ = writeln!(out, "    unknown: {}", e);
+            return hex_lines(out, layout::config(), data);
+        }
+    };
+    let _ = writeln!(
+        out,
+        "    sensor {:#04x}, polling rate {}, lift-off distance {}, rgb effect {:?}",
+        conf.sensor_id, conf.polling_rate, conf.lod, conf.rgb_current_effect
+    );
+    for (i, prof) in conf.dpi_profiles.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "    dpi {}{} {} {} {}",
+            i + 1,
+            if i == conf.dpi_current_profile as usize {
+                "*"
+            } else {
+                " "
+            },
+            prof.value,
+            prof.color,
+            if prof.enabled { "enabled" } else { "disabled" }
+        );
+    }
+    match conf.official().to_raw() {
+        Ok(encoded) => {
+            for p in verify::round_trip(&report, &encoded) {
+                let _ = writeln!(out, "    unknown octet {}", p);
+            }
+        }
+        Err(e) => {
+            let _ = writeln!(out, "    unknown: {}", e);
+        }
+    }
+}
+
+fn describe_buttonmap(out: &mut String, data: &[u8]) {
+    match decode::buttonmap(data) {
+        Ok(map) => {
+            for (i, action) in map.iter().enumerate() {
+                let _ = writeln!(out, "    {}:{}", i + 1, action);
+            }
+        }
+        Err(e) => {
+            let _ = writeln!(out, "    unknown: {}", e);
+            hex_lines(out, layout::buttonmap(), data);
+        }
+    }
+}
+
+fn describe(out: &mut String, selected: &mut Option<u8>, t: &ReportTransfer) {
+    let dir = match t.request {
+        Request::SetReport => "SET",
+        Request::GetReport => "GET",
+    };
+    let _ = write!(out, "{:10.6} {} {} ", t.time, dir, t.report_id);
+    let cmd = t.data.get(1).copied().unwrap_or(0);
+    let arg = t.data.get(2).copied().unwrap_or(0);
+    match (t.request, t.report_id) {
+        (Request::SetReport, HW_REPORT_MSG) => {
+            *selected = Some(cmd);
+            match command_name(cmd) {
+                Some(_) if cmd == HW_CMD_DEBOUNCE && arg != 0 => {
+                    let _ = writeln!(out, "set debounce time to {} ms", u16::from(arg) * 2);
+                }
+                Some(name) => {
+                    let _ = writeln!(out, "select {}", name);
+                }
+                None => {
+                    let _ = writeln!(
+                        out,
+                        "unknown command {:#04x}: {}",
+                        cmd,
+                        hex::encode(&t.data)
+                    );
+                }
+            }
+        }
+        (Request::GetReport, HW_REPORT_MSG) => match cmd {
+            HW_CMD_VER => match decode::version(&t.data) {
+                Ok(v) => {
+                    let _ = writeln!(out, "version {}", v);
+                }
+                Err(_) => {
+                    let _ = writeln!(out, "unknown version: {}", hex::encode(&t.data));
+                }
+            },
+            HW_CMD_DEBOUNCE => {
+                let _ = writeln!(out, "debounce time {} ms", u16::from(arg) * 2);
+            }
+            _ => {
+                let _ = writeln!(
+                    out,
+                    "unknown reply to command {:#04x}: {}",
+                    cmd,
+                    hex::encode(&t.data)
+                );
+            }
+        },
+        (Request::GetReport, HW_REPORT_DATA) => match *selected {
+            Some(HW_CMD_CONF) => {
+                let _ = writeln!(out, "config");
+                describe_config(out, &t.data);
+            }
+            Some(HW_CMD_MAP) => {
+                let _ = writeln!(out, "button map");
+                describe_buttonmap(out, &t.data);
+            }
+            sel => {
+                let _ = writeln!(
+                    out,
+                    "unknown data for command {}",
+                    sel.map_or("none".to_string(), |c| format!("{:#04x}", c))
+                );
+                hex_lines(out, &[], &t.data);
+            }
+        },
+        (Request::SetReport, HW_REPORT_DATA) => {
+            let magic = t.data.get(3).copied().unwrap_or(0);
+            match cmd {
+                HW_CMD_CONF => {
+                    let _ = writeln!(out, "write config");
+                    if magic != HW_CONF_WRITE_MAGIC {
+                        let _ = writeln!(out, "    unknown write magic {:#04x}", magic);
+                    }
+                    describe_config(out, &t.data);
+                }
+                HW_CMD_MAP => {
+                    let _ = writeln!(out, "write button map");
+                    if magic != HW_MAP_WRITE_MAGIC {
+                        let _ = writeln!(out, "    unknown write magic {:#04x}", magic);
+                    }
+                    describe_buttonmap(out, &t.data);
+                }
+                HW_CMD_MACRO if arg == 2 => match decode::macro_bank(&t.data) {
+                    Ok(m) => {
+                        let _ = writeln!(out, "write macro bank {}", m.bank_number);
+                        for ev in &m.events {
+                            let _ = writeln!(out, "    {}", ev);
+                        }
+                    }
+                    Err(e) => {
+                        let _ = writeln!(out, "write macro bank, unknown: {}", e);
+                        hex_lines(out, layout::macro_bank(), &t.data);
+                    }
+                },
+                _ => {
+                    let _ = writeln!(out, "unknown write for command {:#04x}", cmd);
+                    hex_lines(out, &[], &t.data);
+                }
+            }
+        }
+        _ => {
+            let _ = writeln!(out, "unknown report: {}", hex::encode(&t.data));
+        }
+    }
+}
+
+/// A line per transfer, followed by its decoded contents. Everything which is
+/// not understood is marked with "unknown".
+pub fn transcript(transfers: &[ReportTransfer]) -> String {
+    let mut out = String::new();
+    let mut selected = None;
+    for t in transfers {
+        describe(&mut out, &mut selected, t);
+    }
+    out
+}
@@ -1,31 +1,48 @@
-use std::{convert::TryFrom, str::FromStr};
+use std::{
+    cmp::Ordering, convert::TryFrom, fmt, ops::RangeInclusive, str::FromStr, time::Duration,
+};
 
-use anyhow::{anyhow, Result};
 use arrayvec::ArrayVec;
 use bitflags::bitflags;
 use hex::FromHex;
 use hidapi::{HidApi, HidDevice};
 use num_enum::TryFromPrimitive;
+use serde::{Deserialize, Serialize};
 
+use crate::discovery::FoundDevice;
+use crate::error::{Error, Result};
+use crate::model::{Model, Quirk, Registry};
 use crate::protocol::{decode, encode};
+use crate::transport::Transport;
+use crate::validate::validate_buttonmap;
+use crate::verify;
 
 use self::macros::Event;
 
-// Glorious Model O
-const ID_VENDOR: u16 = 0x258a;
-const ID_PRODUCT: u16 = 0x0036;
-const CONTROL_IF: i32 = 1;
-const HW_REPORT_MSG: u8 = 5;
-const HW_REPORT_DATA: u8 = 4;
-const HW_CMD_VER: u8 = 1;
-const HW_CMD_CONF: u8 = 0x11;
-const HW_CMD_MAP: u8 = 0x12;
-const HW_CONF_WRITE_MAGIC: u8 = 0x7b;
-const HW_MAP_WRITE_MAGIC: u8 = 0x50;
+// The product IDs of each model are listed in `model::MODELS`.
+pub(crate) const ID_VENDOR: u16 = 0x258a;
+pub(crate) const HW_REPORT_MSG: u8 = 5;
+pub(crate) const HW_REPORT_DATA: u8 = 4;
+pub(crate) const HW_CMD_VER: u8 = 1;
+pub(crate) const HW_CMD_CONF: u8 = 0x11;
+pub(crate) const HW_CMD_MAP: u8 = 0x12;
+pub(crate) const HW_CMD_DEBOUNCE: u8 = 0x1a;
+pub(crate) const HW_CMD_MACRO: u8 = 0x30;
+pub(crate) const HW_CONF_WRITE_MAGIC: u8 = 0x7b;
+pub(crate) const HW_MAP_WRITE_MAGIC: u8 = 0x50;
 
 pub type DataReport = [u8; 520];
 
-#[derive(Debug, Copy, Clone, Default)]
+/// Debounce times accepted by `set_debounce`, in milliseconds. This is the range
+/// offered by the official software, the firmware itself does not check it.
+pub const DEBOUNCE_RANGE_MS: RangeInclusive<u16> = 2..=16;
+
+/// Lift-off distance levels accepted by the firmware, the higher the value,
+/// the further the mouse can be lifted before it stops tracking.
+pub const LOD_RANGE: RangeInclusive<u8> = 1..=2;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -45,30 +62,130 @@ impl FromStr for Color {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = hex::FromHexError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::from_str(&s)
+    }
+}
+
+impl From<Color> for String {
+    fn from(c: Color) -> String {
+        c.to_string()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
 pub enum DpiValue {
     Double(u16, u16),
     Single(u16),
 }
 
-#[derive(Debug, Copy, Clone)]
+impl FromStr for DpiValue {
+    type Err = Error;
+
+    /// Parse either a single value or independent X and Y values as `XxY`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('x') {
+            Some((x, y)) => Ok(DpiValue::Double(u16::from_str(x)?, u16::from_str(y)?)),
+            None => Ok(DpiValue::Single(u16::from_str(s)?)),
+        }
+    }
+}
+
+impl Default for DpiValue {
+    fn default() -> Self {
+        DpiValue::Single(100)
+    }
+}
+
+impl fmt::Display for DpiValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DpiValue::Single(v) => write!(f, "{}", v),
+            DpiValue::Double(x, y) => write!(f, "{}x{}", x, y),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DpiProfile {
     pub enabled: bool,
     pub value: DpiValue,
     pub color: Color,
 }
 
-#[derive(Debug, Eq, PartialEq, TryFromPrimitive, Clone, Copy)]
+#[derive(Debug, Default, Eq, PartialEq, TryFromPrimitive, Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "u16", into = "u16")]
 #[repr(u8)]
 pub enum PollingRate {
     Hz125 = 1,
     Hz250 = 2,
     Hz500 = 3,
+    #[default]
     Hz1000 = 4,
 }
 
+impl PollingRate {
+    pub fn hz(self) -> u16 {
+        match self {
+            PollingRate::Hz125 => 125,
+            PollingRate::Hz250 => 250,
+            PollingRate::Hz500 => 500,
+            PollingRate::Hz1000 => 1000,
+        }
+    }
+}
+
+impl TryFrom<u16> for PollingRate {
+    type Error = Error;
+
+    fn try_from(hz: u16) -> Result<Self, Self::Error> {
+        match hz {
+            125 => Ok(PollingRate::Hz125),
+            250 => Ok(PollingRate::Hz250),
+            500 => Ok(PollingRate::Hz500),
+            1000 => Ok(PollingRate::Hz1000),
+            _ => Err(parse_error!("Unsupported polling rate {} Hz", hz)),
+        }
+    }
+}
+
+impl From<PollingRate> for u16 {
+    fn from(p: PollingRate) -> u16 {
+        p.hz()
+    }
+}
+
+impl FromStr for PollingRate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hz = s.strip_suffix("Hz").unwrap_or(s).trim();
+        PollingRate::try_from(u16::from_str(hz)?)
+    }
+}
+
+impl fmt::Display for PollingRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} Hz", self.hz())
+    }
+}
+
 pub mod rgb {
+    use std::{fmt, str::FromStr};
+
+    use crate::error::{Error, Result};
     use num_enum::TryFromPrimitive;
+    use serde::{Deserialize, Serialize};
 
     use self::params::{
         Breathing, ConstantRgb, Glorious, Random, Rave, SeamlessBreathing, SingleBreathing,
@@ -76,9 +193,13 @@ pub mod rgb {
     };
     use super::Color;
 
-    #[derive(Debug, Eq, PartialEq, TryFromPrimitive, Clone, Copy)]
+    #[derive(
+        Debug, Default, Eq, PartialEq, TryFromPrimitive, Clone, Copy, Serialize, Deserialize,
+    )]
+    #[serde(rename_all = "kebab-case")]
     #[repr(u8)]
     pub enum Effect {
+        #[default]
         Off = 0,
         Glorious = 1,
         SingleColor = 2,
@@ -92,7 +213,7 @@ pub mod rgb {
         SingleBreathing = 10,
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
     pub struct EffectParameters {
         pub glorious: Glorious,
         pub single_color: SingleColor,
@@ -106,70 +227,217 @@ pub mod rgb {
         pub single_breathing: SingleBreathing,
     }
 
-    // TODO: These should eventually be enums
-    pub type Direction = u8;
-    pub type Speed = u8;
-    pub type Brightness = u8;
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum LevelRepr {
+        Name(String),
+        Raw(u8),
+    }
+
+    /// Define a newtype over the raw value of an effect parameter, with named
+    /// constants for the levels accepted by the firmware. Values read from the
+    /// mouse are kept as they are, even if they are not one of the levels, but
+    /// values given by the user have to be one of them.
+    macro_rules! level {
+        (
+            $(#[$meta:meta])*
+            $name:ident, $what:literal, mask = $mask:literal, default = $default:ident,
+            [$(($konst:ident, $val:literal, $str:literal)),+ $(,)?]
+        ) => {
+            $(#[$meta])*
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            pub struct $name(u8);
+
+            impl Default for $name {
+                fn default() -> Self {
+                    $name::$default
+                }
+            }
+
+            impl $name {
+                $(pub const $konst: $name = $name($val);)+
+
+                /// The levels accepted by the firmware, from the lowest one.
+                pub const LEVELS: &'static [$name] = &[$($name::$konst),+];
+                /// Names of the `LEVELS`, in the same order.
+                pub const NAMES: &'static [&'static str] = &[$($str),+];
+
+                pub fn new(raw: u8) -> Result<Self> {
+                    let v = $name(raw);
+                    if v.is_valid() {
+                        Ok(v)
+                    } else {
+                        Err(Error::encode(
+                            $what,
+                            format!(
+                                "{} is not a valid level, expected one of {}",
+                                raw,
+                                Self::NAMES.join(", ")
+                            ),
+                        ))
+                    }
+                }
+
+                /// Wrap a value as stored in the report without validating it.
+                /// Only the bits which fit in the field are kept.
+                pub const fn from_raw(raw: u8) -> Self {
+                    $name(raw & $mask)
+                }
+
+                pub fn raw(self) -> u8 {
+                    self.0
+                }
+
+                pub fn is_valid(self) -> bool {
+                    Self::LEVELS.contains(&self)
+                }
+
+                pub fn name(self) -> Option<&'static str> {
+                    Self::LEVELS
+                        .iter()
+                        .position(|l| *l == self)
+                        .map(|i| Self::NAMES[i])
+                }
+            }
+
+            impl fmt::Display for $name {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    match self.name() {
+                        Some(name) => f.write_str(name),
+                        None => write!(f, "{}", self.0),
+                    }
+                }
+            }
+
+            impl FromStr for $name {
+                type Err = Error;
+
+                /// Parse either one of the `NAMES` or the raw value of a level.
+                fn from_str(s: &str) -> Result<Self, Self::Err> {
+                    match Self::NAMES.iter().position(|n| *n == s) {
+                        Some(i) => Ok(Self::LEVELS[i]),
+                        None => u8::from_str(s)
+                            .map_err(|_| {
+                                parse_error!(
+                                    "Invalid {} '{}', expected one of {}",
+                                    $what,
+                                    s,
+                                    Self::NAMES.join(", ")
+                                )
+                            })
+                            .and_then(Self::new),
+                    }
+                }
+            }
+
+            impl Serialize for $name {
+                fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                    match self.name() {
+                        Some(name) => s.serialize_str(name),
+                        None => s.serialize_u8(self.0),
+                    }
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $name {
+                fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+                    use serde::de::Error;
+
+                    match LevelRepr::deserialize(d)? {
+                        LevelRepr::Name(s) => Self::from_str(&s),
+                        LevelRepr::Raw(raw) => Self::new(raw),
+                    }
+                    .map_err(D::Error::custom)
+                }
+            }
+        };
+    }
+
+    level!(
+        /// Direction of the Glorious effect, stored in a whole octet.
+        Direction, "direction", mask = 0xff, default = DOWN,
+        [(DOWN, 0, "down"), (UP, 1, "up")]
+    );
+
+    level!(
+        /// Speed of an effect, stored in the lower nibble of the "BS" octet.
+        Speed, "speed", mask = 0x0f, default = MEDIUM,
+        [(SLOW, 1, "slow"), (MEDIUM, 2, "medium"), (FAST, 3, "fast")]
+    );
+
+    level!(
+        /// Brightness of an effect, stored in the upper nibble of the "BS"
+        /// octet. The names are percentages of the full brightness.
+        Brightness, "brightness", mask = 0x0f, default = FULL,
+        [
+            (OFF, 0, "0"),
+            (QUARTER, 1, "25"),
+            (HALF, 2, "50"),
+            (THREE_QUARTERS, 3, "75"),
+            (FULL, 4, "100"),
+        ]
+    );
 
     #[allow(dead_code)]
     pub mod params {
         use super::{Brightness, Color, Direction, Speed};
         use arrayvec::ArrayVec;
+        use serde::{Deserialize, Serialize};
 
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
         pub struct Glorious {
             pub speed: Speed,
             pub direction: Direction,
         }
 
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
         pub struct SingleColor {
             pub brightness: Brightness,
             pub color: Color,
         }
 
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
         pub struct Breathing {
             pub speed: Speed,
             pub count: u8,
             pub colors: ArrayVec<[Color; 7]>,
         }
 
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
         pub struct Tail {
             pub speed: Speed,
             pub brightness: Brightness,
         }
 
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
         pub struct SeamlessBreathing {
             pub speed: Speed,
         }
 
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
         pub struct ConstantRgb {
             pub colors: ArrayVec<[Color; 6]>,
         }
 
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
         pub struct Rave {
             pub speed: Speed,
             pub brightness: Brightness,
             pub colors: ArrayVec<[Color; 2]>,
         }
 
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
         pub struct Random {
             pub speed: Speed,
         }
 
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
         pub struct Wave {
             pub speed: Speed,
             pub brightness: Brightness,
         }
 
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
         pub struct SingleBreathing {
             pub speed: Speed,
             pub color: Color,
@@ -177,7 +445,7 @@ pub mod rgb {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Config {
     pub header: ArrayVec<[u8; 9]>,
     pub sensor_id: u8,
@@ -190,37 +458,233 @@ pub struct Config {
     pub rgb_effect_parameters: rgb::EffectParameters,
     pub unknown: (ArrayVec<[u8; 12]>, u8),
     pub lod: u8,
+    pub ignored: Ignored,
+}
+
+/// Parts of the config report which the firmware ignores, kept as they were
+/// read so that a config is written back unchanged. They default to the values
+/// written by the official software, see `Config::official`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ignored {
+    /// Nibbles of the "BS" octets of effects which do not use them, named
+    /// after the level they would hold.
+    pub glorious_brightness: u8,
+    pub single_color_speed: u8,
+    pub breathing_brightness: u8,
+    pub seamless_breathing_brightness: u8,
+    pub constant_rgb_brightness: u8,
+    pub constant_rgb_speed: u8,
+    pub random_brightness: u8,
+    pub single_breathing_brightness: u8,
+    /// The second half of the DPI values when the axes are not independent.
+    pub dpi_values: [u8; 8],
+}
+
+impl Default for Ignored {
+    fn default() -> Self {
+        let (full, off) = (rgb::Brightness::FULL.raw(), rgb::Brightness::OFF.raw());
+        Ignored {
+            glorious_brightness: full,
+            single_color_speed: 0,
+            breathing_brightness: full,
+            seamless_breathing_brightness: full,
+            constant_rgb_brightness: off,
+            constant_rgb_speed: 0,
+            random_brightness: off,
+            single_breathing_brightness: off,
+            dpi_values: [0; 8],
+        }
+    }
 }
 
 impl Config {
     pub fn from_raw(raw: &DataReport) -> Result<Config> {
-        decode::config_report(raw)
-            .map(|(_, c)| c)
-            .map_err(|_| anyhow::Error::msg("Failed to parse config report"))
-
-        // decode::config_report(raw)
-        //     .map(|(_, c)| c)
-        //     .map_err(|e| From::from(e.map_input(|i| i.to_owned())))
+        decode::config(raw)
     }
 
-    pub fn to_raw(&self) -> DataReport {
+    pub fn to_raw(&self) -> Result<DataReport> {
         encode::config_report(self)
     }
 
+    /// The config with its ignored parts as the official software writes
+    /// them, as a mouse using the known layout is expected to report them.
+    pub fn official(&self) -> Config {
+        Config {
+            ignored: Ignored::default(),
+            ..self.clone()
+        }
+    }
+
+    pub fn set_lod(&mut self, lod: u8) -> Result<()> {
+        if !LOD_RANGE.contains(&lod) {
+            return Err(Error::encode(
+                "lod",
+                format!(
+                    "must be between {} and {}",
+                    LOD_RANGE.start(),
+                    LOD_RANGE.end()
+                ),
+            ));
+        }
+        self.lod = lod;
+        Ok(())
+    }
+
+    /// Update the profile count to the number of enabled profiles. The
+    /// profiles, the active one and the axis independence are left as they
+    /// are.
     pub fn fixup_dpi_metadata(&mut self) {
-        let mut count = 0;
-        let mut indep = false;
-        for prof in &self.dpi_profiles {
-            if prof.enabled {
-                count += 1;
-            }
-            if let DpiValue::Double(_, _) = prof.value {
-                indep = true;
+        self.dpi_profile_count = self.dpi_profiles.iter().filter(|p| p.enabled).count() as u8;
+    }
+
+    /// Make the axes independent if a profile gives a value for each of them,
+    /// which can only be stored that way.
+    pub fn enable_independent_axes_if_needed(&mut self) {
+        if self
+            .dpi_profiles
+            .iter()
+            .any(|p| matches!(p.value, DpiValue::Double(_, _)))
+        {
+            self.dpi_axes_independent = true;
+        }
+    }
+
+    /// Make the first enabled DPI profile the active one.
+    pub fn select_first_enabled_dpi(&mut self) {
+        if let Some(i) = self.dpi_profiles.iter().position(|p| p.enabled) {
+            self.dpi_current_profile = i as u8;
+        }
+    }
+
+    fn check_dpi_index(&self, i: usize) -> Result<()> {
+        if i >= self.dpi_profiles.len() {
+            return Err(Error::encode(
+                "dpi_profiles",
+                format!("there is no profile {}", i + 1),
+            ));
+        }
+        Ok(())
+    }
+
+    fn is_only_enabled_dpi_profile(&self, i: usize) -> bool {
+        self.dpi_profiles[i].enabled && self.dpi_profiles.iter().filter(|p| p.enabled).count() <= 1
+    }
+
+    /// Enable or disable the DPI profile in slot `i`. The last enabled
+    /// profile cannot be disabled, and disabling the active one makes the
+    /// first enabled profile active.
+    pub fn set_dpi_enabled(&mut self, i: usize, enabled: bool) -> Result<()> {
+        self.check_dpi_index(i)?;
+        if !enabled && self.is_only_enabled_dpi_profile(i) {
+            return Err(Error::encode(
+                "dpi_profiles",
+                "at least one profile has to stay enabled",
+            ));
+        }
+        self.dpi_profiles[i].enabled = enabled;
+        if !enabled && self.dpi_current_profile as usize == i {
+            self.select_first_enabled_dpi();
+        }
+        self.fixup_dpi_metadata();
+        Ok(())
+    }
+
+    /// Make the DPI profile in slot `i` the active one. It has to be enabled.
+    pub fn set_dpi_current(&mut self, i: usize) -> Result<()> {
+        self.check_dpi_index(i)?;
+        if !self.dpi_profiles[i].enabled {
+            return Err(Error::encode(
+                "dpi_current_profile",
+                format!("profile {} is disabled", i + 1),
+            ));
+        }
+        self.dpi_current_profile = i as u8;
+        Ok(())
+    }
+
+    /// Insert a profile at slot `i`, shifting the following ones towards the
+    /// end. The last slot has to be disabled, as it is dropped.
+    pub fn insert_dpi_profile(&mut self, i: usize, prof: DpiProfile) -> Result<()> {
+        self.check_dpi_index(i)?;
+        if self.dpi_profiles.last().is_some_and(|p| p.enabled) {
+            return Err(Error::encode(
+                "dpi_profiles",
+                "there is no free profile slot",
+            ));
+        }
+        self.dpi_profiles.pop();
+        self.dpi_profiles.insert(i, prof);
+        if self.dpi_current_profile as usize >= i {
+            self.dpi_current_profile += 1;
+        }
+        self.fixup_dpi_metadata();
+        Ok(())
+    }
+
+    /// Remove the profile in slot `i`, shifting the following ones towards the
+    /// start. The freed last slot is disabled. Removing the active profile
+    /// makes the first enabled one active.
+    pub fn remove_dpi_profile(&mut self, i: usize) -> Result<DpiProfile> {
+        self.check_dpi_index(i)?;
+        if self.is_only_enabled_dpi_profile(i) {
+            return Err(Error::encode(
+                "dpi_profiles",
+                "at least one profile has to stay enabled",
+            ));
+        }
+        let removed = self.dpi_profiles.remove(i);
+        self.dpi_profiles.push(DpiProfile {
+            enabled: false,
+            ..removed
+        });
+        match (self.dpi_current_profile as usize).cmp(&i) {
+            Ordering::Greater => self.dpi_current_profile -= 1,
+            Ordering::Equal => self.select_first_enabled_dpi(),
+            Ordering::Less => {}
+        }
+        self.fixup_dpi_metadata();
+        Ok(removed)
+    }
+
+    /// Move the profile in slot `from` to slot `to`, shifting the ones in
+    /// between. The active profile stays the same.
+    pub fn move_dpi_profile(&mut self, from: usize, to: usize) -> Result<()> {
+        self.check_dpi_index(from)?;
+        self.check_dpi_index(to)?;
+        let prof = self.dpi_profiles.remove(from);
+        self.dpi_profiles.insert(to, prof);
+        let current = self.dpi_current_profile as usize;
+        self.dpi_current_profile = if current == from {
+            to
+        } else if from < current && current <= to {
+            current - 1
+        } else if to <= current && current < from {
+            current + 1
+        } else {
+            current
+        } as u8;
+        self.fixup_dpi_metadata();
+        Ok(())
+    }
+}
+
+/// Format a set of flags as their names joined by '+', the inverse of the
+/// `FromStr` implementations of the flag types below.
+fn fmt_flags(f: &mut fmt::Formatter<'_>, bits: u32, names: &[(u32, &str)]) -> fmt::Result {
+    if bits == 0 {
+        return f.write_str("none");
+    }
+    let mut first = true;
+    for (flag, name) in names {
+        if bits & flag != 0 {
+            if !first {
+                f.write_str("+")?;
             }
+            f.write_str(name)?;
+            first = false;
         }
-        self.dpi_profile_count = count;
-        self.dpi_axes_independent = indep;
     }
+    Ok(())
 }
 
 bitflags! {
@@ -241,24 +705,40 @@ impl TryFrom<u8> for Modifier {
 }
 
 impl FromStr for Modifier {
-    type Err = anyhow::Error;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let split = s.split('+');
-        let mut ret = Modifier::from_bits(0).unwrap();
+        let mut ret = Modifier::empty();
         for w in split {
             ret |= match w {
+                "none" => Ok(Modifier::empty()),
                 "ctrl" => Ok(Modifier::CTRL),
                 "shift" => Ok(Modifier::SHIFT),
                 "alt" => Ok(Modifier::ALT),
                 "super" | "win" => Ok(Modifier::SUPER),
-                _ => Err(anyhow!("Unknown modifier '{}'", w)),
+                _ => Err(parse_error!("Unknown modifier '{}'", w)),
             }?;
         }
         Ok(ret)
     }
 }
 
+impl fmt::Display for Modifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_flags(
+            f,
+            self.bits().into(),
+            &[
+                (Self::CTRL.bits().into(), "ctrl"),
+                (Self::SHIFT.bits().into(), "shift"),
+                (Self::ALT.bits().into(), "alt"),
+                (Self::SUPER.bits().into(), "super"),
+            ],
+        )
+    }
+}
+
 bitflags! {
     pub struct MouseButton: u8 {
         const LEFT    = 0x01;
@@ -278,17 +758,37 @@ impl TryFrom<u8> for MouseButton {
 }
 
 impl FromStr for MouseButton {
-    type Err = anyhow::Error;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "left" => Ok(Self::LEFT),
-            "right" => Ok(Self::RIGHT),
-            "middle" => Ok(Self::MIDDLE),
-            "back" => Ok(Self::BACK),
-            "forward" => Ok(Self::FORWARD),
-            _ => Err(anyhow!("Invalid mouse button '{}'", s)),
+        let mut ret = Self::empty();
+        for w in s.split('+') {
+            ret |= match w {
+                "left" => Ok(Self::LEFT),
+                "right" => Ok(Self::RIGHT),
+                "middle" => Ok(Self::MIDDLE),
+                "back" => Ok(Self::BACK),
+                "forward" => Ok(Self::FORWARD),
+                _ => Err(parse_error!("Invalid mouse button '{}'", w)),
+            }?;
         }
+        Ok(ret)
+    }
+}
+
+impl fmt::Display for MouseButton {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_flags(
+            f,
+            self.bits().into(),
+            &[
+                (Self::LEFT.bits().into(), "left"),
+                (Self::RIGHT.bits().into(), "right"),
+                (Self::MIDDLE.bits().into(), "middle"),
+                (Self::BACK.bits().into(), "back"),
+                (Self::FORWARD.bits().into(), "forward"),
+            ],
+        )
     }
 }
 
@@ -310,35 +810,63 @@ bitflags! {
 }
 
 impl FromStr for MediaButton {
-    type Err = anyhow::Error;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "home" | "home-page" => Ok(Self::HOME_PAGE),
-            "player" | "media-player" => Ok(Self::MEDIA_PLAYER),
-            "explorer" => Ok(Self::EXPLORER),
-            "mail" | "email" => Ok(Self::EMAIL),
-            "calc" | "calculator" => Ok(Self::CALCULATOR),
-            "next" => Ok(Self::NEXT),
-            "prev" | "previous" => Ok(Self::PREVIOUS),
-            "stop" => Ok(Self::STOP),
-            "playpause" | "play-pause" => Ok(Self::PLAY_PAUSE),
-            "mute" | "toggle-mute" => Ok(Self::MUTE),
-            "vol-up" | "volume-up" => Ok(Self::VOLUME_UP),
-            "vol-down" | "volume-down" => Ok(Self::VOLUME_DOWN),
-            _ => Err(anyhow!("Invalid media button '{}'", s)),
+        let mut ret = Self::empty();
+        for w in s.split('+') {
+            ret |= match w {
+                "home" | "home-page" => Ok(Self::HOME_PAGE),
+                "player" | "media-player" => Ok(Self::MEDIA_PLAYER),
+                "explorer" => Ok(Self::EXPLORER),
+                "mail" | "email" => Ok(Self::EMAIL),
+                "calc" | "calculator" => Ok(Self::CALCULATOR),
+                "next" => Ok(Self::NEXT),
+                "prev" | "previous" => Ok(Self::PREVIOUS),
+                "stop" => Ok(Self::STOP),
+                "playpause" | "play-pause" => Ok(Self::PLAY_PAUSE),
+                "mute" | "toggle-mute" => Ok(Self::MUTE),
+                "vol-up" | "volume-up" => Ok(Self::VOLUME_UP),
+                "vol-down" | "volume-down" => Ok(Self::VOLUME_DOWN),
+                _ => Err(parse_error!("Invalid media button '{}'", w)),
+            }?;
         }
+        Ok(ret)
+    }
+}
+
+impl fmt::Display for MediaButton {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_flags(
+            f,
+            self.bits(),
+            &[
+                (Self::HOME_PAGE.bits(), "home"),
+                (Self::MEDIA_PLAYER.bits(), "player"),
+                (Self::EXPLORER.bits(), "explorer"),
+                (Self::EMAIL.bits(), "mail"),
+                (Self::CALCULATOR.bits(), "calc"),
+                (Self::NEXT.bits(), "next"),
+                (Self::PREVIOUS.bits(), "prev"),
+                (Self::STOP.bits(), "stop"),
+                (Self::PLAY_PAUSE.bits(), "playpause"),
+                (Self::MUTE.bits(), "mute"),
+                (Self::VOLUME_UP.bits(), "vol-up"),
+                (Self::VOLUME_DOWN.bits(), "vol-down"),
+            ],
+        )
     }
 }
 
 pub mod buttonmap {
-    use std::str::FromStr;
+    use std::{convert::TryFrom, fmt, str::FromStr};
 
-    use anyhow::{anyhow, Context};
+    use crate::error::Error;
+    use serde::{Deserialize, Serialize};
 
     use super::{MediaButton, Modifier, MouseButton};
 
-    #[derive(Debug, Copy, Clone)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     #[repr(u8)]
     pub enum DpiSwitch {
         Cycle = 0,
@@ -347,26 +875,64 @@ pub mod buttonmap {
     }
 
     impl FromStr for DpiSwitch {
-        type Err = anyhow::Error;
+        type Err = Error;
 
         fn from_str(s: &str) -> Result<Self, Self::Err> {
             match s {
                 "cycle" | "loop" => Ok(Self::Cycle),
                 "up" => Ok(Self::Up),
                 "down" => Ok(Self::Down),
-                _ => Err(anyhow!("Invalid DPI switch action")),
+                _ => Err(parse_error!("Invalid DPI switch action")),
             }
         }
     }
 
-    #[derive(Debug, Copy, Clone)]
+    impl fmt::Display for DpiSwitch {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(match self {
+                Self::Cycle => "loop",
+                Self::Up => "up",
+                Self::Down => "down",
+            })
+        }
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum MacroMode {
         Burst(u8),
         RepeatUntilRelease,
         RepeatUntilAnotherPress,
     }
 
-    #[derive(Debug, Copy, Clone)]
+    impl FromStr for MacroMode {
+        type Err = Error;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "until-release" => Ok(Self::RepeatUntilRelease),
+                "until-press" => Ok(Self::RepeatUntilAnotherPress),
+                s => Ok(Self::Burst(u8::from_str(s).map_err(|_| {
+                    parse_error!(
+                        "Invalid macro mode '{}', expected a repeat count, 'until-release' or 'until-press'",
+                        s
+                    )
+                })?)),
+            }
+        }
+    }
+
+    impl fmt::Display for MacroMode {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::Burst(c) => write!(f, "{}", c),
+                Self::RepeatUntilRelease => f.write_str("until-release"),
+                Self::RepeatUntilAnotherPress => f.write_str("until-press"),
+            }
+        }
+    }
+
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(try_from = "String", into = "String")]
     pub enum ButtonAction {
         MouseButton(MouseButton),
         Scroll(i8),
@@ -382,6 +948,7 @@ pub mod buttonmap {
             modifiers: Modifier,
             key: u8,
         },
+        #[default]
         Disabled,
         Macro(u8, MacroMode),
     }
@@ -396,7 +963,7 @@ pub mod buttonmap {
     ];
 
     impl FromStr for ButtonAction {
-        type Err = anyhow::Error;
+        type Err = Error;
 
         fn from_str(s: &str) -> Result<Self, Self::Err> {
             if s == "disable" {
@@ -404,7 +971,7 @@ pub mod buttonmap {
             }
             let (branch, data) = s
                 .split_once(':')
-                .context("Action must be 'disable' or have parameters")?;
+                .ok_or_else(|| parse_error!("Action must be 'disable' or have parameters"))?;
             match branch {
                 "mouse" => Ok(Self::MouseButton(MouseButton::from_str(data)?)),
                 "scroll" => Ok(Self::Scroll(match data {
@@ -415,7 +982,7 @@ pub mod buttonmap {
                 "repeat" => {
                     let parts: Vec<&str> = data.split(':').collect();
                     if parts.len() < 2 || parts.len() > 3 {
-                        Err(anyhow!(
+                        Err(parse_error!(
                             "Action 'repeat' takes 2 or 3 parameters separated by colons (key:count[:interval])"
                         ))
                     } else {
@@ -433,11 +1000,14 @@ pub mod buttonmap {
                 "dpi" => Ok(Self::DpiSwitch(DpiSwitch::from_str(data)?)),
                 "dpi-lock" => Ok(Self::DpiLock(u16::from_str(data)?)),
                 "media" => Ok(Self::MediaButton(MediaButton::from_str(data)?)),
-                "macro" => Ok(Self::Macro(u8::from_str(data)?, MacroMode::Burst(1))), // TODO other repeat modes
+                "macro" => match data.split_once(':') {
+                    Some((bank, mode)) => Ok(Self::Macro(u8::from_str(bank)?, mode.parse()?)),
+                    None => Ok(Self::Macro(u8::from_str(data)?, MacroMode::Burst(1))),
+                },
                 "keyboard" => {
                     let parts: Vec<&str> = data.split(':').collect();
                     if parts.len() != 2 {
-                        Err(anyhow!(
+                        Err(parse_error!(
                             "Action 'keyboard' takes 2 parameters separated by colons (modifiers:key)"
                         ))
                     } else {
@@ -447,19 +1017,58 @@ pub mod buttonmap {
                         })
                     }
                 }
-                "disable" => Err(anyhow!("Action 'disable' does not take any parameter")),
-                _ => Err(anyhow!("Unknown action type '{}'", branch)),
+                "disable" => Err(parse_error!("Action 'disable' does not take any parameter")),
+                _ => Err(parse_error!("Unknown action type '{}'", branch)),
             }
         }
     }
+
+    /// Formats the action in the syntax accepted by `from_str`.
+    impl fmt::Display for ButtonAction {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::MouseButton(b) => write!(f, "mouse:{}", b),
+                Self::Scroll(amount) => write!(f, "scroll:{}", amount),
+                Self::RepeatButton {
+                    which,
+                    interval,
+                    count,
+                } => write!(f, "repeat:{}:{}:{}", which, count, interval),
+                Self::DpiSwitch(sw) => write!(f, "dpi:{}", sw),
+                Self::DpiLock(dpi) => write!(f, "dpi-lock:{}", dpi),
+                Self::MediaButton(b) => write!(f, "media:{}", b),
+                Self::KeyboardShortcut { modifiers, key } => {
+                    write!(f, "keyboard:{}:{}", modifiers, key)
+                }
+                Self::Disabled => f.write_str("disable"),
+                Self::Macro(bank, MacroMode::Burst(1)) => write!(f, "macro:{}", bank),
+                Self::Macro(bank, mode) => write!(f, "macro:{}:{}", bank, mode),
+            }
+        }
+    }
+
+    impl TryFrom<String> for ButtonAction {
+        type Error = Error;
+
+        fn try_from(s: String) -> Result<Self, Self::Error> {
+            Self::from_str(&s)
+        }
+    }
+
+    impl From<ButtonAction> for String {
+        fn from(a: ButtonAction) -> String {
+            a.to_string()
+        }
+    }
 }
 
 pub mod macros {
     use super::{Modifier, MouseButton};
-    use anyhow::anyhow;
-    use std::str::FromStr;
+    use crate::error::{Error, Result};
+    use serde::{Deserialize, Serialize};
+    use std::{convert::TryFrom, fmt, str::FromStr};
 
-    #[derive(Debug, Copy, Clone)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     #[repr(u8)]
     pub enum EventType {
         Keyboard(u8),
@@ -468,35 +1077,55 @@ pub mod macros {
     }
 
     impl EventType {
-        fn from_str_params(type_: &str, arg: &str) -> anyhow::Result<Self> {
+        fn from_str_params(type_: &str, arg: &str) -> Result<Self> {
             match type_ {
                 "keyboard" => Ok(Self::Keyboard(u8::from_str(arg)?)),
                 "modifier" => Ok(Self::Modifier(Modifier::from_str(arg)?)),
                 "mouse" => Ok(Self::Mouse(MouseButton::from_str(arg)?)),
-                _ => Err(anyhow!("Unknown event type '{}'", type_)),
+                _ => Err(parse_error!("Unknown event type '{}'", type_)),
             }
         }
     }
 
-    #[derive(Debug, Copy, Clone)]
+    impl fmt::Display for EventType {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::Keyboard(k) => write!(f, "keyboard:{}", k),
+                Self::Modifier(m) => write!(f, "modifier:{}", m),
+                Self::Mouse(b) => write!(f, "mouse:{}", b),
+            }
+        }
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum State {
         Up,
         Down,
     }
 
     impl FromStr for State {
-        type Err = anyhow::Error;
+        type Err = Error;
 
         fn from_str(s: &str) -> Result<Self, Self::Err> {
             match s {
                 "up" | "release" => Ok(Self::Up),
                 "down" | "press" => Ok(Self::Down),
-                _ => Err(anyhow!("Invalid state '{}'", s)),
+                _ => Err(parse_error!("Invalid state '{}'", s)),
             }
         }
     }
 
-    #[derive(Debug, Copy, Clone)]
+    impl fmt::Display for State {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(match self {
+                Self::Up => "up",
+                Self::Down => "down",
+            })
+        }
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(try_from = "String", into = "String")]
     pub struct Event {
         pub state: State,
         pub evtype: EventType,
@@ -504,12 +1133,15 @@ pub mod macros {
     }
 
     impl FromStr for Event {
-        type Err = anyhow::Error;
+        type Err = Error;
 
         fn from_str(s: &str) -> Result<Self, Self::Err> {
             let split: Vec<&str> = s.split(':').collect();
             if split.len() != 4 {
-                Err(anyhow!("Incorrect number of parameters ({})", split.len()))
+                Err(parse_error!(
+                    "Incorrect number of parameters ({})",
+                    split.len()
+                ))
             } else {
                 Ok(Self {
                     state: State::from_str(split[0])?,
@@ -520,56 +1152,180 @@ pub mod macros {
         }
     }
 
+    /// Formats the event in the syntax accepted by `from_str`.
+    impl fmt::Display for Event {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}:{}:{}", self.state, self.evtype, self.duration)
+        }
+    }
+
+    impl TryFrom<String> for Event {
+        type Error = Error;
+
+        fn try_from(s: String) -> Result<Self, Self::Error> {
+            Self::from_str(&s)
+        }
+    }
+
+    impl From<Event> for String {
+        fn from(e: Event) -> String {
+            e.to_string()
+        }
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
     pub struct Macro {
+        #[serde(rename = "bank")]
         pub bank_number: u8,
         pub events: Vec<Event>,
     }
+
+    /// A macro bank report, keeping the octet gloryctl does not interpret so
+    /// that it encodes back unchanged.
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct MacroReport {
+        pub bank: Macro,
+        /// The octet after the bank number, maybe part of it.
+        pub unknown: u8,
+    }
 }
 
-pub type ButtonMapping = [buttonmap::ButtonAction; 6];
-pub struct GloriousDevice {
-    pub hiddev: HidDevice,
+/// Number of button actions in the button map report, of which each model
+/// uses as many as it has buttons.
+pub const MAX_BUTTONS: usize = 20;
+
+pub type ButtonMapping = ArrayVec<[buttonmap::ButtonAction; MAX_BUTTONS]>;
+
+/// A button map report, keeping the octets gloryctl does not interpret so
+/// that it encodes back unchanged. They default to the values written by the
+/// official software.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ButtonMapReport {
+    pub header: ArrayVec<[u8; 6]>,
+    /// The octet after the button count.
+    pub unknown: u8,
+    pub mapping: ButtonMapping,
+    /// The octets of every action slot as read, of which those after the
+    /// mapping are written back. Slots past its end are written as disabled.
+    pub slots: Vec<u8>,
 }
 
-impl GloriousDevice {
-    pub fn open_first(hid: &HidApi) -> Result<GloriousDevice> {
-        let devinfo = hid
-            .device_list()
-            .filter(|dev| {
-                dev.product_id() == ID_PRODUCT
-                    && dev.vendor_id() == ID_VENDOR
-                    && dev.interface_number() == CONTROL_IF
-            })
-            .next()
-            .ok_or(anyhow!("Could not find a supported device."))?;
-        let dev = devinfo.open_device(hid)?;
-        let gdev = GloriousDevice { hiddev: dev };
-        return Ok(gdev);
+impl Default for ButtonMapReport {
+    fn default() -> Self {
+        ButtonMapReport {
+            header: ArrayVec::from([HW_REPORT_DATA, HW_CMD_MAP, 0, 0, 0, 0]),
+            unknown: 0,
+            mapping: ButtonMapping::new(),
+            slots: Vec::new(),
+        }
+    }
+}
+
+/// Time the firmware is given to process a write before the next request.
+const WRITE_DELAY: Duration = Duration::from_millis(20);
+
+/// `WRITE_DELAY` for models with the `Quirk::SlowWrites` quirk.
+const SLOW_WRITE_DELAY: Duration = Duration::from_millis(100);
+
+pub struct GloriousDevice<T: Transport = HidDevice> {
+    pub transport: T,
+    model: Model,
+    verify_retries: Option<u32>,
+}
+
+impl GloriousDevice<HidDevice> {
+    /// Open the first connected mouse of any of the models in the registry.
+    pub fn open_first(hid: &HidApi, registry: &Registry) -> Result<GloriousDevice> {
+        let devices = FoundDevice::list(hid, registry);
+        let found = devices.first().ok_or(Error::DeviceNotFound)?;
+        Self::open(hid, found)
+    }
+
+    /// Open a device found by `FoundDevice::list` or `FoundDevice::with_id`.
+    pub fn open(hid: &HidApi, found: &FoundDevice) -> Result<GloriousDevice> {
+        match hid.open_path(&found.path) {
+            Ok(dev) => {
+                let mut dev = GloriousDevice::new(dev);
+                dev.set_model(found.model.clone());
+                Ok(dev)
+            }
+            Err(e) => {
+                // hidapi does not report why opening failed, so check the
+                // device node directly to give a useful hint.
+                let path = found.path_lossy();
+                match std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&path)
+                {
+                    Err(io) if io.kind() == std::io::ErrorKind::PermissionDenied => {
+                        Err(Error::PermissionDenied { path })
+                    }
+                    _ => Err(Error::Hid(e)),
+                }
+            }
+        }
+    }
+}
+
+impl<T: Transport> GloriousDevice<T> {
+    pub fn new(transport: T) -> Self {
+        GloriousDevice {
+            transport,
+            model: Model::default(),
+            verify_retries: None,
+        }
+    }
+
+    /// The model of the mouse, which determines the values accepted by
+    /// `send_config`, `send_buttonmap` and `send_macro_bank`.
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    /// Enable or disable the verify mode. When enabled, `send_config` reads
+    /// the config back after writing it, and writes it again up to `retries`
+    /// more times until it matches. If it still differs, `Error::Mismatch`
+    /// lists the fields involved. The button mapping cannot be verified, as
+    /// the mouse always reports the default one.
+    pub fn set_verify(&mut self, retries: Option<u32>) {
+        self.verify_retries = retries;
     }
 
     pub fn read_fw_version(&self) -> Result<String> {
         let mut buf = [HW_REPORT_MSG, HW_CMD_VER, 0, 0, 0, 0];
-        self.hiddev.send_feature_report(&buf)?;
-        self.hiddev.get_feature_report(&mut buf)?;
+        self.transport.send_feature_report(&buf)?;
+        self.transport.get_feature_report(&mut buf)?;
         decode::version(&buf)
-            .map(|(_, c)| c.to_string())
-            .map_err(|_| anyhow::Error::msg("Failed to parse firmware version"))
+    }
+
+    /// Time the firmware of the model needs to process a write.
+    fn write_delay(&self) -> Duration {
+        if self.model.has_quirk(Quirk::SlowWrites) {
+            SLOW_WRITE_DELAY
+        } else {
+            WRITE_DELAY
+        }
     }
 
     pub fn send_msg(&self, a: u8, s: u8) -> Result<()> {
         let buf = [HW_REPORT_MSG, a, s, 0, 0, 0];
-        self.hiddev.send_feature_report(&buf)?;
-        std::thread::sleep(std::time::Duration::from_millis(20));
+        self.transport.send_feature_report(&buf)?;
+        self.transport.delay(self.write_delay());
         Ok(())
     }
 
     fn read_data(&self, cmd: u8) -> Result<DataReport> {
         let req = [HW_REPORT_MSG, cmd, 0, 0, 0, 0];
-        self.hiddev.send_feature_report(&req)?;
+        self.transport.send_feature_report(&req)?;
         let mut buf = [0; 520];
         buf[0] = HW_REPORT_DATA;
-        self.hiddev.get_feature_report(&mut buf)?;
-        return Ok(buf);
+        self.transport.get_feature_report(&mut buf)?;
+        Ok(buf)
     }
 
     pub fn read_config_raw(&self) -> Result<DataReport> {
@@ -584,25 +1340,40 @@ impl GloriousDevice {
         self.read_config_raw().map(|c| Config::from_raw(&c))?
     }
 
+    /// Read the config in order to modify and write it back. This fails with
+    /// `Error::RoundTrip` if the parts of the report which the firmware
+    /// ignores differ from what the official software writes, as the mouse
+    /// then probably uses them for something gloryctl does not know about.
+    pub fn read_config_checked(&self) -> Result<Config> {
+        let raw = self.read_config_raw()?;
+        let conf = Config::from_raw(&raw)?;
+        let problems = verify::round_trip(&raw, &conf.official().to_raw()?);
+        if problems.is_empty() {
+            Ok(conf)
+        } else {
+            Err(Error::RoundTrip(problems))
+        }
+    }
+
     pub fn read_buttonmap(&self) -> Result<ButtonMapping> {
         let raw = self.read_buttonmap_raw()?;
         decode::buttonmap(&raw)
-            .map(|(_, c)| c)
-            .map_err(|_| anyhow::Error::msg("Failed to parse button map"))
     }
 
     fn send_data(&mut self, magic3: Option<u8>, data: &DataReport) -> Result<()> {
         // let req = [HW_REPORT_MSG, cmd, 0, 0, 0, 0];
-        // self.hiddev.send_feature_report(&req)?;
+        // self.transport.send_feature_report(&req)?;
         let mut datacpy = data.to_owned();
         if let Some(m) = magic3 {
             datacpy[3] = m;
         }
-        self.hiddev.send_feature_report(&datacpy)?;
+        self.transport.send_feature_report(&datacpy)?;
         // The mouse sometimes gets confused when reading the config right after
-        // writing it. Wait a bit just in case. 10ms seems to be probably enough,
-        // doing 20 for good measure.
-        std::thread::sleep(std::time::Duration::from_millis(20));
+        // writing it. Wait a bit just in case. 10ms seems to be probably enough
+        // for the Model O, so WRITE_DELAY doubles that for good measure. Models
+        // with the slow-writes quirk get SLOW_WRITE_DELAY, 100ms, a margin
+        // wide enough for firmware which has not been timed.
+        self.transport.delay(self.write_delay());
         Ok(())
     }
 
@@ -614,18 +1385,91 @@ impl GloriousDevice {
         self.send_data(Some(HW_MAP_WRITE_MAGIC), data)
     }
 
+    /// Validate the config and write it to the mouse, see `set_verify`.
     pub fn send_config(&mut self, conf: &Config) -> Result<()> {
-        let x = conf.to_raw();
-        self.send_config_raw(&x)
+        conf.validate(&self.model)?;
+        let x = conf.to_raw()?;
+        self.send_config_raw(&x)?;
+        let retries = match self.verify_retries {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        let mut attempts = 1;
+        loop {
+            let problems = verify::config(conf, &self.read_config()?)?;
+            if problems.is_empty() {
+                return Ok(());
+            }
+            if attempts > retries {
+                return Err(Error::Mismatch { attempts, problems });
+            }
+            self.send_config_raw(&x)?;
+            attempts += 1;
+        }
     }
 
+    /// Validate the button mapping and write it to the mouse.
     pub fn send_buttonmap(&mut self, map: &ButtonMapping) -> Result<()> {
-        let x = encode::buttonmap(&map);
-        self.send_config_raw(&x)
+        validate_buttonmap(map, &self.model)?;
+        let x = encode::buttonmap(map)?;
+        self.send_buttonmap_raw(&x)
+    }
+
+    /// Check that the model has the given macro bank.
+    pub(crate) fn check_macro_bank(&self, bank: u8) -> Result<()> {
+        if bank >= self.model.macro_banks {
+            return Err(Error::encode(
+                "macro bank",
+                format!(
+                    "the {} has {} banks, numbered from 0",
+                    self.model.name, self.model.macro_banks
+                ),
+            ));
+        }
+        Ok(())
     }
 
     pub fn send_macro_bank(&mut self, bank: u8, events: &[Event]) -> Result<()> {
+        self.check_macro_bank(bank)?;
         let x = encode::macro_bank(bank, events)?;
         self.send_data(None, &x)
     }
+
+    fn check_debounce_supported(&self) -> Result<()> {
+        if self.model.has_quirk(Quirk::NoDebounce) {
+            return Err(Error::Unsupported {
+                model: self.model.name.to_string(),
+                feature: "setting the debounce time",
+            });
+        }
+        Ok(())
+    }
+
+    /// Read the configured debounce time in milliseconds.
+    pub fn read_debounce(&self) -> Result<u16> {
+        self.check_debounce_supported()?;
+        let mut buf = [HW_REPORT_MSG, HW_CMD_DEBOUNCE, 0, 0, 0, 0];
+        self.transport.send_feature_report(&buf)?;
+        self.transport.get_feature_report(&mut buf)?;
+        Ok(u16::from(buf[2]) * 2)
+    }
+
+    /// Set the debounce time. The mouse only supports even values, so `ms` is
+    /// rounded up to one. The time actually configured is returned.
+    pub fn set_debounce(&mut self, ms: u16) -> Result<u16> {
+        self.check_debounce_supported()?;
+        if !DEBOUNCE_RANGE_MS.contains(&ms) {
+            return Err(Error::encode(
+                "debounce time",
+                format!(
+                    "must be between {} and {} ms",
+                    DEBOUNCE_RANGE_MS.start(),
+                    DEBOUNCE_RANGE_MS.end()
+                ),
+            ));
+        }
+        let d = ms.div_ceil(2) as u8;
+        self.send_msg(HW_CMD_DEBOUNCE, d)?;
+        Ok(u16::from(d) * 2)
+    }
 }
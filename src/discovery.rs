@@ -0,0 +1,196 @@
+//! Finding the connected mice and choosing the one to talk to, for when more
+//! than one is attached, or running a command on each of them.
+
+use std::ffi::CString;
+use std::fmt;
+
+use hidapi::{DeviceInfo, HidApi};
+
+use crate::error::{Error, Result};
+use crate::model::{Model, Registry};
+use crate::transport::Transport;
+
+/// A connected device, as found by `FoundDevice::list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundDevice {
+    /// The hidraw node of its control interface.
+    pub path: CString,
+    /// The USB serial number, if the device reports one.
+    pub serial: Option<String>,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub model: Model,
+}
+
+impl FoundDevice {
+    fn from_info(devinfo: &DeviceInfo, model: Model) -> Self {
+        FoundDevice {
+            path: devinfo.path().to_owned(),
+            serial: devinfo
+                .serial_number()
+                .filter(|s| !s.is_empty())
+                .map(str::to_owned),
+            vendor_id: devinfo.vendor_id(),
+            product_id: devinfo.product_id(),
+            model,
+        }
+    }
+
+    /// The connected mice of any of the models in the registry, sorted by
+    /// path so that their indices stay the same between runs.
+    pub fn list(hid: &HidApi, registry: &Registry) -> Vec<FoundDevice> {
+        let mut devices: Vec<_> = hid
+            .device_list()
+            .filter_map(|dev| {
+                let model = registry.find(dev.vendor_id(), dev.product_id())?;
+                if model.control_interface == dev.interface_number() {
+                    Some(Self::from_info(dev, model.clone()))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        devices.sort_by(|a, b| a.path.cmp(&b.path));
+        devices
+    }
+
+    /// The connected devices with the given USB IDs and interface, which do
+    /// not need to be in the registry. Unknown devices are assumed to be a
+    /// Model O.
+    pub fn with_id(
+        hid: &HidApi,
+        vendor: u16,
+        product: u16,
+        interface: i32,
+        registry: &Registry,
+    ) -> Vec<FoundDevice> {
+        let model = registry.find(vendor, product).cloned().unwrap_or_default();
+        let mut devices: Vec<_> = hid
+            .device_list()
+            .filter(|dev| {
+                dev.vendor_id() == vendor
+                    && dev.product_id() == product
+                    && dev.interface_number() == interface
+            })
+            .map(|dev| Self::from_info(dev, model.clone()))
+            .collect();
+        devices.sort_by(|a, b| a.path.cmp(&b.path));
+        devices
+    }
+
+    /// Choose a device by its hidraw path, its serial number or its index in
+    /// the list, counted from 1, in that order.
+    pub fn select<'a>(devices: &'a [FoundDevice], selector: &str) -> Result<&'a FoundDevice> {
+        if let Some(dev) = devices
+            .iter()
+            .find(|d| d.path.to_bytes() == selector.as_bytes())
+        {
+            return Ok(dev);
+        }
+        let by_serial: Vec<_> = devices
+            .iter()
+            .filter(|d| d.serial.as_deref() == Some(selector))
+            .collect();
+        match by_serial.len() {
+            0 => {}
+            1 => return Ok(by_serial[0]),
+            count => {
+                return Err(Error::AmbiguousDevice {
+                    selector: selector.to_string(),
+                    count,
+                })
+            }
+        }
+        selector
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| devices.get(i.checked_sub(1)?))
+            .ok_or_else(|| Error::NoSuchDevice {
+                selector: selector.to_string(),
+            })
+    }
+
+    /// The device chosen by `selector`, or the first one if none is given.
+    pub fn select_or_first<'a>(
+        devices: &'a [FoundDevice],
+        selector: Option<&str>,
+    ) -> Result<&'a FoundDevice> {
+        match selector {
+            Some(s) => Self::select(devices, s),
+            None => devices.first().ok_or(Error::DeviceNotFound),
+        }
+    }
+
+    /// The hidraw path, for display.
+    pub fn path_lossy(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+}
+
+/// Its path, model and serial number, as shown by `gloryctl list`.
+impl fmt::Display for FoundDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}  {}  serial {}",
+            self.path_lossy(),
+            self.model.name,
+            self.serial.as_deref().unwrap_or("-")
+        )
+    }
+}
+
+/// The outcome of `run_on_each` for every device.
+pub struct Summary {
+    pub results: Vec<(FoundDevice, Result<(), String>)>,
+}
+
+impl Summary {
+    pub fn failed(&self) -> usize {
+        self.results.iter().filter(|(_, r)| r.is_err()).count()
+    }
+
+    /// Fail if the operation failed on any of the devices.
+    pub fn check(&self) -> Result<()> {
+        match self.failed() {
+            0 => Ok(()),
+            failed => Err(Error::Failed {
+                failed,
+                total: self.results.len(),
+            }),
+        }
+    }
+}
+
+/// One line per device, numbered from 1, telling whether the operation
+/// succeeded or why it failed.
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (found, result)) in self.results.iter().enumerate() {
+            match result {
+                Ok(()) => writeln!(f, "{}: {}  ok", i + 1, found)?,
+                Err(e) => writeln!(f, "{}: {}  failed: {}", i + 1, found, e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run `op` on each device, carrying on after a failure. A device whose
+/// transport could not be opened counts as failed without running `op`.
+pub fn run_on_each<'a, E: fmt::Display>(
+    devices: Vec<(FoundDevice, Result<Box<dyn Transport + 'a>>)>,
+    mut op: impl FnMut(&FoundDevice, Box<dyn Transport + 'a>) -> Result<(), E>,
+) -> Summary {
+    let results = devices
+        .into_iter()
+        .map(|(found, transport)| {
+            let result = match transport {
+                Ok(transport) => op(&found, transport).map_err(|e| format!("{:#}", e)),
+                Err(e) => Err(e.to_string()),
+            };
+            (found, result)
+        })
+        .collect();
+    Summary { results }
+}
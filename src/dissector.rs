@@ -0,0 +1,294 @@
+//! Generation of a Lua dissector for Wireshark from the report layouts in
+//! `protocol::layout`, so that the fields shown in captures stay in sync with
+//! the ones known to the encoder and decoder.
+
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+use crate::device::{
+    rgb::Effect, HW_CMD_CONF, HW_CMD_MACRO, HW_CMD_MAP, HW_CMD_VER, HW_REPORT_DATA, HW_REPORT_MSG,
+};
+use crate::model::MODELS;
+use crate::protocol::layout::{self, Field, Kind};
+
+/// The layouts described by the dissector, with the prefix of their fields.
+fn described_layouts() -> [(&'static str, &'static [Field]); 4] {
+    [
+        ("config", layout::config()),
+        ("buttonmap", layout::buttonmap()),
+        ("macro", layout::macro_bank()),
+        ("version", layout::version()),
+    ]
+}
+
+/// The Lua code dissecting the layouts, see `lua_dissector`.
+const DISSECT: &str = r#"
+local function add_fields(tree, buf, layout)
+    for _, fld in ipairs(layout) do
+        local offset, len, kind, pf = fld[1], fld[2], fld[3], fld[4]
+        if offset + len > buf:len() then
+            break
+        end
+        local range = buf(offset, len)
+        if kind == "nibbles" then
+            tree:add(pf, range)
+            tree:add(fld[5], range)
+        elseif kind == "bs" then
+            local sub = tree:add(pf, range)
+            sub:add(f["brightness"], range)
+            sub:add(f["speed"], range)
+        elseif kind == "dpi" then
+            local sub = tree:add(pf, range)
+            for i = 0, len - 1 do
+                local v = buf(offset + i, 1)
+                sub:add(f["dpi"], v):append_text(string.format(" (%d DPI)", (v:uint() + 1) * 100))
+            end
+        elseif kind == "rgb" or kind == "rbg" then
+            local sub = tree:add(pf, range)
+            for i = 0, len - 3, 3 do
+                local c = buf(offset + i, 3)
+                local r, g, b = c(0, 1):uint(), c(1, 1):uint(), c(2, 1):uint()
+                if kind == "rbg" then
+                    g, b = b, g
+                end
+                sub:add(f["color"], c):append_text(string.format(" (#%02x%02x%02x)", r, g, b))
+            end
+        elseif kind == "action" then
+            local sub = tree:add(pf, range)
+            sub:add(f["action.type"], buf(offset, 1))
+            sub:add(f["action.params"], buf(offset + 1, len - 1))
+        elseif kind == "events" then
+            local sub = tree:add(pf, range)
+            local count = math.min(buf(macro_count_offset, 1):uint(), math.floor(len / 3))
+            for i = 0, count - 1 do
+                local e = buf(offset + 3 * i, 3)
+                local ev = sub:add(e, "Event " .. (i + 1))
+                ev:add(f["event.state"], e(0, 1))
+                ev:add(f["event.type"], e(0, 1))
+                ev:add(f["event.duration"], e(0, 2))
+                ev:add(f["event.key"], e(2, 1))
+            end
+        else
+            tree:add(pf, range)
+        end
+    end
+end
+
+function proto.dissector(buf, pinfo, tree)
+    if buf:len() < 2 then
+        return 0
+    end
+    local id, cmd = buf(0, 1):uint(), buf(1, 1):uint()
+    if id ~= report_msg and id ~= report_data then
+        return 0
+    end
+    pinfo.cols.protocol = proto.name
+    pinfo.cols.info = string.format("Report %d, %s", id,
+        commands[cmd] or string.format("unknown command 0x%02x", cmd))
+    local sub = tree:add(proto, buf())
+    sub:add(f["report_id"], buf(0, 1))
+    sub:add(f["command"], buf(1, 1))
+    if id == report_msg then
+        if cmd == cmd_version and buf:len() >= 6 and buf(2, 1):uint() ~= 0 then
+            add_fields(sub, buf, layouts["version"])
+        elseif buf:len() >= 3 then
+            sub:add(f["argument"], buf(2, 1))
+        end
+    elseif data_layouts[cmd] then
+        add_fields(sub, buf, data_layouts[cmd])
+    end
+    return buf:len()
+end
+"#;
+
+fn value_string(out: &mut String, name: &str, values: &[(u8, String)]) {
+    let _ = writeln!(out, "local {} = {{", name);
+    for (v, s) in values {
+        let _ = writeln!(out, "    [{:#04x}] = {:?},", v, s);
+    }
+    let _ = writeln!(out, "}}");
+}
+
+fn owned(values: &[(u8, &str)]) -> Vec<(u8, String)> {
+    values.iter().map(|(v, s)| (*v, s.to_string())).collect()
+}
+
+/// The key of a field in the Lua table of fields, also used as the
+/// Wireshark field name after "gloryctl.".
+fn key(prefix: &str, name: &str) -> String {
+    format!("{}.{}", prefix, name.replace(' ', "_"))
+}
+
+fn proto_field(out: &mut String, key: &str, label: &str, typ: &str, rest: &str) {
+    let _ = writeln!(
+        out,
+        "f[{:?}] = ProtoField.{}({:?}, {:?}{})",
+        key,
+        typ,
+        format!("gloryctl.{}", key),
+        label,
+        rest
+    );
+}
+
+/// A Wireshark dissector in Lua for the reports exchanged with the mouse. It
+/// registers itself for the USB vendor and product IDs of every model.
+pub fn lua_dissector() -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "-- Wireshark dissector for the configuration protocol of Glorious mice.\n\
+         -- Generated by `gloryctl dissector` from src/protocol/layout.rs, do not edit.\n\
+         -- Copy it to ~/.local/lib/wireshark/plugins/ to use it.\n\n\
+         local proto = Proto(\"gloryctl\", \"Glorious mouse configuration\")\n"
+    );
+
+    let _ = writeln!(out, "local report_msg = {}", HW_REPORT_MSG);
+    let _ = writeln!(out, "local report_data = {}", HW_REPORT_DATA);
+    let _ = writeln!(out, "local cmd_version = {:#04x}", HW_CMD_VER);
+    let count = layout::macro_bank()
+        .iter()
+        .find(|f| f.name == "event count")
+        .map_or(0, |f| f.offset);
+    let _ = writeln!(out, "local macro_count_offset = {}\n", count);
+
+    value_string(&mut out, "commands", &owned(layout::COMMANDS));
+    let effects: Vec<_> = (0..=u8::MAX)
+        .filter_map(|v| Effect::try_from(v).ok().map(|e| (v, format!("{:?}", e))))
+        .collect();
+    value_string(&mut out, "effects", &effects);
+    value_string(&mut out, "button_actions", &owned(layout::BUTTON_ACTIONS));
+    value_string(&mut out, "event_types", &owned(layout::MACRO_EVENTS));
+    value_string(
+        &mut out,
+        "event_states",
+        &[(0, "down".to_string()), (1, "up".to_string())],
+    );
+
+    let _ = writeln!(out, "\nlocal f = {{}}");
+    proto_field(&mut out, "report_id", "Report ID", "uint8", "");
+    proto_field(
+        &mut out,
+        "command",
+        "Command",
+        "uint8",
+        ", base.HEX, commands",
+    );
+    proto_field(&mut out, "argument", "Argument", "uint8", ", base.HEX");
+    proto_field(&mut out, "dpi", "DPI value", "uint8", "");
+    proto_field(&mut out, "color", "Color", "bytes", "");
+    proto_field(
+        &mut out,
+        "brightness",
+        "Brightness",
+        "uint8",
+        ", base.DEC, nil, 0xf0",
+    );
+    proto_field(&mut out, "speed", "Speed", "uint8", ", base.DEC, nil, 0x0f");
+    proto_field(
+        &mut out,
+        "action.type",
+        "Action",
+        "uint8",
+        ", base.HEX, button_actions",
+    );
+    proto_field(&mut out, "action.params", "Parameters", "bytes", "");
+    proto_field(
+        &mut out,
+        "event.state",
+        "State",
+        "uint8",
+        ", base.DEC, event_states, 0x80",
+    );
+    proto_field(
+        &mut out,
+        "event.type",
+        "Type",
+        "uint8",
+        ", base.DEC, event_types, 0x70",
+    );
+    proto_field(
+        &mut out,
+        "event.duration",
+        "Duration",
+        "uint16",
+        ", base.DEC, nil, 0x0fff",
+    );
+    proto_field(&mut out, "event.key", "Key", "uint8", ", base.HEX");
+
+    let mut defined = Vec::new();
+    let mut layouts = String::new();
+    for (prefix, fields) in described_layouts().iter() {
+        let _ = writeln!(layouts, "layouts[{:?}] = {{", prefix);
+        for fld in fields.iter() {
+            let k = key(prefix, fld.name);
+            let (kind, typ, rest) = match fld.kind {
+                Kind::Raw => ("raw", "bytes", ""),
+                Kind::Number => ("number", "uint8", ""),
+                Kind::Nibbles => ("nibbles", "", ""),
+                Kind::Mask => ("number", "uint8", ", base.HEX"),
+                Kind::Dpi => ("dpi", "bytes", ""),
+                Kind::ColorsRgb => ("rgb", "bytes", ""),
+                Kind::ColorsRbg => ("rbg", "bytes", ""),
+                Kind::BrightnessSpeed => ("bs", "uint8", ", base.HEX"),
+                Kind::Effect => ("number", "uint8", ", base.DEC, effects"),
+                Kind::ButtonAction => ("action", "bytes", ""),
+                Kind::MacroEvents => ("events", "bytes", ""),
+                Kind::Text => ("raw", "string", ""),
+            };
+            if fld.kind == Kind::Nibbles {
+                let mut names = fld.name.splitn(2, '/');
+                let upper = names.next().unwrap_or(fld.name);
+                let lower = names.next().unwrap_or(fld.name);
+                let (ku, kl) = (key(prefix, upper), key(prefix, lower));
+                if !defined.contains(&ku) {
+                    proto_field(&mut out, &ku, upper, "uint8", ", base.DEC, nil, 0xf0");
+                    proto_field(&mut out, &kl, lower, "uint8", ", base.DEC, nil, 0x0f");
+                    defined.push(ku.clone());
+                }
+                let _ = writeln!(
+                    layouts,
+                    "    {{ {}, {}, {:?}, f[{:?}], f[{:?}] }},",
+                    fld.offset, fld.len, kind, ku, kl
+                );
+                continue;
+            }
+            if !defined.contains(&k) {
+                proto_field(&mut out, &k, fld.name, typ, rest);
+                defined.push(k.clone());
+            }
+            let _ = writeln!(
+                layouts,
+                "    {{ {}, {}, {:?}, f[{:?}] }},",
+                fld.offset, fld.len, kind, k
+            );
+        }
+        let _ = writeln!(layouts, "}}");
+    }
+    let _ = writeln!(
+        out,
+        "\nlocal fields = {{}}\n\
+         for _, pf in pairs(f) do\n    table.insert(fields, pf)\nend\n\
+         proto.fields = fields\n"
+    );
+    let _ = writeln!(out, "local layouts = {{}}\n{}", layouts);
+    let _ = writeln!(
+        out,
+        "local data_layouts = {{\n    [{:#04x}] = layouts[\"config\"],\n    \
+         [{:#04x}] = layouts[\"buttonmap\"],\n    [{:#04x}] = layouts[\"macro\"],\n}}",
+        HW_CMD_CONF, HW_CMD_MAP, HW_CMD_MACRO
+    );
+    out.push_str(DISSECT);
+    out.push('\n');
+    for model in MODELS {
+        for product in model.product_ids.iter() {
+            let _ = writeln!(
+                out,
+                "DissectorTable.get(\"usb.product\"):add({:#010x}, proto) -- {}",
+                (u32::from(model.vendor_id) << 16) | u32::from(*product),
+                model.name
+            );
+        }
+    }
+    out
+}
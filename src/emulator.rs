@@ -0,0 +1,273 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use hex::FromHex;
+
+use crate::device::{
+    DataReport, HW_CMD_CONF, HW_CMD_DEBOUNCE, HW_CMD_MACRO, HW_CMD_MAP, HW_CMD_VER,
+    HW_CONF_WRITE_MAGIC, HW_MAP_WRITE_MAGIC, HW_REPORT_DATA, HW_REPORT_MSG,
+};
+use crate::error::{Error, Result};
+use crate::model::Model;
+use crate::protocol::encode;
+use crate::transport::Transport;
+
+/// Number of octets of the config report actually used by the firmware.
+const CONFIG_LEN: usize = 131;
+
+/// The config buffer shown in the README, as returned by a real mouse.
+#[rustfmt::skip]
+const DEFAULT_CONFIG: [u8; CONFIG_LEN] = [
+    0x04, 0x11, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x64, 0x06, 0x04, 0x23, 0xf2, 0x04, 0x05, 0x05,
+    0x05, 0x06, 0x06, 0x07, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0x00, 0xc0,
+    0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0xff, 0x00, 0xff, 0x00, 0xff, 0xff, 0xff, 0xff, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x41, 0x00, 0x40, 0xff, 0x00, 0x00, 0x42, 0x03, 0xff, 0x00,
+    0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x42, 0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x42, 0xff, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x42, 0x02, 0xff, 0x00,
+    0x00, 0x01, 0x00,
+];
+
+struct State {
+    command: u8,
+    version: [u8; 4],
+    debounce: u8,
+    config: DataReport,
+    buttonmap: Option<DataReport>,
+    macro_banks: BTreeMap<u8, DataReport>,
+}
+
+/// An in-process emulation of the Model O firmware.
+///
+/// It implements the bank-switching scheme described in the README: the
+/// command selected by the last write to report 5 determines what a following
+/// read returns. Writes to report 4 are dispatched based on their contents,
+/// just like the real firmware does.
+pub struct EmulatedMouse {
+    state: RefCell<State>,
+}
+
+impl Default for EmulatedMouse {
+    fn default() -> Self {
+        let mut config = [0; 520];
+        config[..CONFIG_LEN].copy_from_slice(&DEFAULT_CONFIG);
+        EmulatedMouse {
+            state: RefCell::new(State {
+                command: 0,
+                version: *b"V103",
+                debounce: 2,
+                config,
+                buttonmap: None,
+                macro_banks: BTreeMap::new(),
+            }),
+        }
+    }
+}
+
+impl EmulatedMouse {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the stored config report, for example with one captured from a real mouse.
+    pub fn with_config(self, config: &DataReport) -> Self {
+        self.state.borrow_mut().config = *config;
+        self
+    }
+
+    pub fn config_raw(&self) -> DataReport {
+        self.state.borrow().config
+    }
+
+    /// The last button map written to the mouse, if any.
+    pub fn buttonmap_raw(&self) -> Option<DataReport> {
+        self.state.borrow().buttonmap
+    }
+
+    pub fn macro_bank_raw(&self, bank: u8) -> Option<DataReport> {
+        self.state.borrow().macro_banks.get(&bank).copied()
+    }
+
+    /// The raw debounce value, the effective time is twice this in milliseconds.
+    pub fn debounce(&self) -> u8 {
+        self.state.borrow().debounce
+    }
+
+    /// Load the emulator state previously stored by `save`. If the file does
+    /// not exist, a mouse in its default state is returned.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(path).map_err(Error::io(path))?;
+        let mouse = Self::default();
+        {
+            let mut st = mouse.state.borrow_mut();
+            for line in text.lines().filter(|l| !l.trim().is_empty()) {
+                let words: Vec<&str> = line.split_whitespace().collect();
+                match words.as_slice() {
+                    ["version", v] => {
+                        st.version = <[u8; 4]>::try_from(v.as_bytes())
+                            .map_err(|_| parse_error!("Invalid firmware version '{}'", v))?
+                    }
+                    ["debounce", d] => st.debounce = u8::from_str(d)?,
+                    ["config", data] => st.config = report_from_hex(data)?,
+                    ["buttonmap", data] => st.buttonmap = Some(report_from_hex(data)?),
+                    ["macro", bank, data] => {
+                        st.macro_banks
+                            .insert(u8::from_str(bank)?, report_from_hex(data)?);
+                    }
+                    _ => return Err(parse_error!("Invalid emulator state line '{}'", line)),
+                }
+            }
+        }
+        Ok(mouse)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let st = self.state.borrow();
+        let mut out = String::new();
+        out.push_str(&format!(
+            "version {}\n",
+            String::from_utf8_lossy(&st.version)
+        ));
+        out.push_str(&format!("debounce {}\n", st.debounce));
+        out.push_str(&format!("config {}\n", hex::encode(&st.config[..])));
+        if let Some(map) = &st.buttonmap {
+            out.push_str(&format!("buttonmap {}\n", hex::encode(&map[..])));
+        }
+        for (bank, data) in &st.macro_banks {
+            out.push_str(&format!("macro {} {}\n", bank, hex::encode(&data[..])));
+        }
+        fs::write(path, out).map_err(Error::io(path))
+    }
+}
+
+fn report_from_hex(s: &str) -> Result<DataReport> {
+    let bytes = Vec::from_hex(s)?;
+    if bytes.len() != 520 {
+        return Err(parse_error!(
+            "Expected a 520 byte report, got {}",
+            bytes.len()
+        ));
+    }
+    let mut report = [0; 520];
+    report.copy_from_slice(&bytes);
+    Ok(report)
+}
+
+impl State {
+    fn write_data(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() < 11 {
+            return Err(transport_error!(
+                "Data report too short ({} octets)",
+                data.len()
+            ));
+        }
+        let mut report = [0; 520];
+        let len = data.len().min(520);
+        report[..len].copy_from_slice(&data[..len]);
+
+        match data[1] {
+            HW_CMD_CONF => {
+                if data[3] != HW_CONF_WRITE_MAGIC {
+                    return Err(transport_error!(
+                        "Config write without magic ({:#04x})",
+                        data[3]
+                    ));
+                }
+                // Only the first 131 octets are stored, the remaining ones read back as 0.
+                // The octets which differ between reads and writes are restored.
+                let mut config = [0; 520];
+                config[..CONFIG_LEN].copy_from_slice(&report[..CONFIG_LEN]);
+                config[3] = 0;
+                config[6] = 6;
+                self.config = config;
+            }
+            HW_CMD_MAP => {
+                // Older versions of gloryctl sent the button map with the config
+                // write magic, which the firmware accepts as well.
+                if data[3] != HW_MAP_WRITE_MAGIC && data[3] != HW_CONF_WRITE_MAGIC {
+                    return Err(transport_error!(
+                        "Button map write without magic ({:#04x})",
+                        data[3]
+                    ));
+                }
+                self.buttonmap = Some(report);
+            }
+            HW_CMD_MACRO if data[2] == 0x02 => {
+                self.macro_banks.insert(data[8], report);
+            }
+            cmd => {
+                return Err(transport_error!(
+                    "Unsupported data report write {:#04x}",
+                    cmd
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn read_data(&self) -> Result<DataReport> {
+        match self.command {
+            HW_CMD_CONF => Ok(self.config),
+            // The firmware returns the default button mapping no matter what was
+            // written, see the README.
+            HW_CMD_MAP => encode::buttonmap(&Model::default().default_buttonmap()),
+            cmd => Err(transport_error!(
+                "Command {:#04x} has no data report to read",
+                cmd
+            )),
+        }
+    }
+}
+
+impl Transport for EmulatedMouse {
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        let mut st = self.state.borrow_mut();
+        match data.first() {
+            Some(&HW_REPORT_MSG) => {
+                let cmd = *data
+                    .get(1)
+                    .ok_or_else(|| transport_error!("Empty message report"))?;
+                let arg = data.get(2).copied().unwrap_or(0);
+                if cmd == HW_CMD_DEBOUNCE && arg != 0 {
+                    st.debounce = arg;
+                }
+                st.command = cmd;
+                Ok(())
+            }
+            Some(&HW_REPORT_DATA) => st.write_data(data),
+            Some(id) => Err(transport_error!("Unknown feature report {}", id)),
+            None => Err(transport_error!("Empty feature report")),
+        }
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize> {
+        let st = self.state.borrow();
+        let report: Vec<u8> = match buf.first() {
+            Some(&HW_REPORT_MSG) => {
+                let mut msg = vec![HW_REPORT_MSG, st.command, 0, 0, 0, 0];
+                match st.command {
+                    HW_CMD_VER => msg[2..].copy_from_slice(&st.version),
+                    HW_CMD_DEBOUNCE => msg[2] = st.debounce,
+                    _ => {}
+                }
+                msg
+            }
+            Some(&HW_REPORT_DATA) => st.read_data()?.to_vec(),
+            Some(id) => return Err(transport_error!("Unknown feature report {}", id)),
+            None => return Err(transport_error!("Empty feature report")),
+        };
+        let len = report.len().min(buf.len());
+        buf[..len].copy_from_slice(&report[..len]);
+        Ok(len)
+    }
+
+    fn delay(&self, _duration: std::time::Duration) {}
+}
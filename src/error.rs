@@ -0,0 +1,194 @@
+use std::fmt;
+use std::io;
+use std::num::ParseIntError;
+use std::path::PathBuf;
+
+/// Errors returned by the library.
+#[derive(Debug)]
+pub enum Error {
+    /// No supported mouse is connected.
+    DeviceNotFound,
+    /// No connected mouse has the path, serial number or index given to
+    /// `FoundDevice::select`.
+    NoSuchDevice { selector: String },
+    /// Several connected mice share the serial number given to
+    /// `FoundDevice::select`.
+    AmbiguousDevice { selector: String, count: usize },
+    /// An operation run on several mice using `run_on_each` failed on
+    /// `failed` of the `total` ones.
+    Failed { failed: usize, total: usize },
+    /// The model of the mouse does not implement a command, see `Quirk`.
+    Unsupported {
+        model: String,
+        feature: &'static str,
+    },
+    /// A supported mouse was found, but the device node cannot be opened by
+    /// the current user.
+    PermissionDenied { path: String },
+    /// Communication with the mouse using hidapi failed.
+    Hid(hidapi::HidError),
+    /// A transport other than hidapi failed, see `Transport`.
+    Transport(String),
+    /// A report read from the mouse could not be decoded. `offset` is the
+    /// position of the offending octet in the report, including the report ID.
+    Decode { offset: usize, field: &'static str },
+    /// A value cannot be encoded into a report, or the mouse does not accept it.
+    Encode { field: &'static str, reason: String },
+    /// Values which would be corrupted when written to the mouse, as found by
+    /// `Config::validate` or `validate_buttonmap`.
+    Invalid(Vec<Problem>),
+    /// With verification enabled, the values read back after a write still
+    /// differed from the written ones after `attempts` writes.
+    Mismatch {
+        attempts: u32,
+        problems: Vec<Problem>,
+    },
+    /// Encoding the config read from the mouse again does not reproduce it, so
+    /// the mouse probably uses a layout gloryctl does not know. Each problem
+    /// names a differing octet.
+    RoundTrip(Vec<Problem>),
+    /// A textual representation of a value, a profile or a session is invalid.
+    Parse(String),
+    /// Reading or writing a file failed.
+    Io { path: PathBuf, source: io::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A single invalid value. `path` names the field as in the Rust structures,
+/// with the elements of arrays numbered from 1 as on the command line, for
+/// example `dpi_profiles[2].value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub path: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.reason)
+    }
+}
+
+impl Error {
+    pub(crate) fn encode(field: &'static str, reason: impl Into<String>) -> Self {
+        Error::Encode {
+            field,
+            reason: reason.into(),
+        }
+    }
+
+    pub(crate) fn io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Error::Io { path, source }
+    }
+}
+
+/// Shorthand for returning `Error::Parse` with a formatted message.
+macro_rules! parse_error {
+    ($($arg:tt)*) => {
+        $crate::error::Error::Parse(format!($($arg)*))
+    };
+}
+
+/// Shorthand for returning `Error::Transport` with a formatted message.
+macro_rules! transport_error {
+    ($($arg:tt)*) => {
+        $crate::error::Error::Transport(format!($($arg)*))
+    };
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DeviceNotFound => write!(f, "Could not find a supported device."),
+            Error::NoSuchDevice { selector } => write!(
+                f,
+                "No supported device has the path, serial number or index '{}'.",
+                selector
+            ),
+            Error::AmbiguousDevice { selector, count } => write!(
+                f,
+                "{} devices have the serial number '{}', select one by path or index.",
+                count, selector
+            ),
+            Error::Failed { failed, total } => {
+                write!(f, "Failed on {} of {} mice.", failed, total)
+            }
+            Error::Unsupported { model, feature } => {
+                write!(f, "The {} does not support {}.", model, feature)
+            }
+            Error::PermissionDenied { path } => write!(
+                f,
+                "Permission denied opening {}, check the permissions of the device node",
+                path
+            ),
+            Error::Hid(e) => write!(f, "HID communication failed: {}", e),
+            Error::Transport(e) => f.write_str(e),
+            Error::Decode { offset, field } => write!(
+                f,
+                "Failed to decode {} at offset {} of the report",
+                field, offset
+            ),
+            Error::Encode { field, reason } => write!(f, "Invalid {}: {}", field, reason),
+            Error::Invalid(problems) => {
+                write!(f, "Refusing to write invalid values:")?;
+                for p in problems {
+                    write!(f, "\n    {}", p)?;
+                }
+                Ok(())
+            }
+            Error::Mismatch { attempts, problems } => {
+                write!(
+                    f,
+                    "The mouse did not apply the written values ({} attempts):",
+                    attempts
+                )?;
+                for p in problems {
+                    write!(f, "\n    {}", p)?;
+                }
+                Ok(())
+            }
+            Error::RoundTrip(problems) => {
+                write!(
+                    f,
+                    "The config read from the mouse changes when encoded again, refusing to write it:"
+                )?;
+                for p in problems {
+                    write!(f, "\n    {}", p)?;
+                }
+                Ok(())
+            }
+            Error::Parse(e) => f.write_str(e),
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Hid(e) => Some(e),
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<hidapi::HidError> for Error {
+    fn from(e: hidapi::HidError) -> Self {
+        Error::Hid(e)
+    }
+}
+
+impl From<ParseIntError> for Error {
+    fn from(e: ParseIntError) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
+
+impl From<hex::FromHexError> for Error {
+    fn from(e: hex::FromHexError) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
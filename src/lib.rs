@@ -1,7 +1,37 @@
+#[macro_use]
+mod error;
+
+mod backup;
+mod capture;
 mod device;
+mod discovery;
+mod dissector;
+mod emulator;
+mod model;
+mod probe;
+mod profile;
 mod protocol;
+mod recording;
+mod transport;
+mod validate;
+mod verify;
 
+pub use backup::Backup;
+pub use capture::{read_usbmon, transcript, ReportTransfer, Request};
 pub use device::{
-    buttonmap::ButtonAction, buttonmap::DEFAULT_MAP, macros, rgb, Color, Config, DataReport,
-    DpiProfile, DpiValue, GloriousDevice,
+    buttonmap::{ButtonAction, DpiSwitch, MacroMode, DEFAULT_MAP},
+    macros, rgb, ButtonMapReport, ButtonMapping, Color, Config, DataReport, DpiProfile, DpiValue,
+    GloriousDevice, Ignored, MediaButton, Modifier, MouseButton, PollingRate, DEBOUNCE_RANGE_MS,
+    MAX_BUTTONS,
 };
+pub use discovery::{run_on_each, FoundDevice, Summary};
+pub use dissector::lua_dissector;
+pub use emulator::EmulatedMouse;
+pub use error::{Error, Problem, Result};
+pub use model::{Model, Quirk, Registry, MODELS};
+pub use probe::Probe;
+pub use profile::{DpiSettings, Profile, RgbSettings};
+pub use protocol::{decode, encode, layout};
+pub use recording::{Exchange, Recorder, Replay};
+pub use transport::Transport;
+pub use validate::validate_buttonmap;
@@ -1,20 +1,83 @@
 use std::convert::TryInto;
+use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::{anyhow, Context, Result};
 
-use clap::{ArgEnum, Clap};
+use clap::Clap;
 use gloryctl::macros::Event;
-use gloryctl::{rgb::Effect, ButtonAction, Color, DpiValue, GloriousDevice};
+use gloryctl::{
+    decode, layout, macros,
+    rgb::{Brightness, Direction, Effect, Speed},
+    Backup, ButtonAction, ButtonMapping, Color, Config, DataReport, DpiProfile, DpiValue,
+    EmulatedMouse, FoundDevice, GloriousDevice, Model, PollingRate, Probe, Problem, Profile,
+    Recorder, Registry, Replay, Transport,
+};
+
+type Device<'a> = GloriousDevice<Box<dyn Transport + 'a>>;
 
 #[derive(Clap)]
 pub struct Opts {
+    /// Talk to an emulated mouse instead of real hardware. Its state is loaded
+    /// from and saved back to the given file, which is created if missing.
+    #[clap(long, value_name = "STATE_FILE")]
+    emulate: Option<PathBuf>,
+
+    /// Log all communication with the mouse to the given file.
+    #[clap(long, value_name = "SESSION_FILE")]
+    record: Option<PathBuf>,
+
+    /// Instead of talking to a mouse, replay the responses of a session
+    /// recorded using --record, failing if the requests differ from it.
+    #[clap(long, value_name = "SESSION_FILE", conflicts_with_all = &["emulate", "record"])]
+    replay: Option<PathBuf>,
+
+    /// The mouse to talk to when several are connected, given by its hidraw
+    /// path, its serial number or its index as shown by 'list'. Defaults to
+    /// the first one.
+    #[clap(long, value_name = "PATH|SERIAL|INDEX", conflicts_with_all = &["emulate", "replay"])]
+    device: Option<String>,
+
+    /// Run the command on every connected mouse instead of one, and print
+    /// whether it succeeded on each. Only for apply, rgb, dpi, button and
+    /// macro.
+    #[clap(long, conflicts_with_all = &["device", "emulate", "replay", "record"])]
+    all: bool,
+
+    /// File remembering the button mapping and macros written to the mouse,
+    /// which cannot be read back from it. Defaults to
+    /// $XDG_STATE_HOME/gloryctl/known-SERIAL.toml, read from known.toml
+    /// until it is first written. Mice without a serial number all share
+    /// known.toml, which gets a warning when several are connected. Not used
+    /// with --emulate or --replay unless given.
+    #[clap(long, value_name = "FILE")]
+    host_state: Option<PathBuf>,
+
+    /// Read the config back after writing it, and fail listing the differing
+    /// fields if it does not match.
+    #[clap(long)]
+    verify: bool,
+
+    /// Number of times to write again when the values read back differ.
+    #[clap(long, value_name = "N", default_value = "2")]
+    verify_retries: u32,
+
     #[clap(subcommand)]
     cmd: Command,
 }
 
 #[derive(Clap)]
 enum Command {
+    #[clap(flatten)]
+    Device(DeviceCommand),
+    #[clap(flatten)]
+    Standalone(StandaloneCommand),
+}
+
+/// Commands run on the selected mouse, or on every one with --all.
+#[derive(Clap)]
+enum DeviceCommand {
     /// Dump the firmware version and Config
     Dump(Dump),
     /// Configure the button mapping
@@ -23,22 +86,81 @@ enum Command {
     Dpi(Dpi),
     /// Configure macros
     Macro(Macro),
+    /// Apply a profile file describing the whole configuration
+    Apply(Apply),
+    /// Export the current configuration as a profile file
+    Export(Export),
+    /// Save an exact copy of the config report to a file
+    Backup(BackupCmd),
+    /// Write a config report saved using 'backup' back to the mouse
+    Restore(Restore),
+    /// Show or configure the polling rate and lift-off distance
+    Sensor(Sensor),
+    /// Show or configure the debounce time of the buttons
+    Debounce(Debounce),
     /// Configure the RGB effect
     // This is weird due to https://github.com/clap-rs/clap/issues/2005
     Rgb {
         #[clap(subcommand)]
         rgbcmd: Rgb,
     },
+    /// Check whether a device speaks the protocol, without writing to it
+    Probe(ProbeCmd),
 }
 
+impl DeviceCommand {
+    /// Whether the command reads or writes the settings of the mouse, rather
+    /// than only probing it. The mouse is sent a message before those
+    /// commands.
+    fn uses_settings(&self) -> bool {
+        !matches!(self, DeviceCommand::Probe(_))
+    }
+}
+
+/// Commands which do not run on a selected mouse.
 #[derive(Clap)]
-struct Dump {}
+enum StandaloneCommand {
+    /// List the connected mice with their path, serial number and firmware
+    List,
+    /// Decode a saved report without talking to the mouse
+    Decode {
+        #[clap(subcommand)]
+        decodecmd: DecodeCmd,
+    },
+    /// Print the reports exchanged with the mouse in a usbmon capture
+    Capture(Capture),
+    /// Print a Wireshark dissector for the reports exchanged with the mouse
+    Dissector,
+}
+
+impl StandaloneCommand {
+    fn run(&self) -> Result<()> {
+        match self {
+            StandaloneCommand::List => list(&hidapi::HidApi::new()?, &Registry::load()?),
+            StandaloneCommand::Decode { decodecmd } => decodecmd.run(),
+            StandaloneCommand::Capture(capture) => capture.run(),
+            StandaloneCommand::Dissector => {
+                print!("{}", gloryctl::lua_dissector());
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Clap)]
+struct Dump {
+    /// Print the raw config and button map reports, with each octet labelled
+    /// by the field it belongs to
+    #[clap(long)]
+    annotate: bool,
+}
 
 #[derive(Clap)]
 #[clap(after_help = r"DISCUSSION:
     The format of a mapping is button:action-type[:action-params...]
-    where button is a number from 1 to 6 and action-type:action-params]
-    is one of the following:
+    where button is a number from 1 to the number of buttons of the mouse
+    (6 for the Model O) and action-type:action-params] is one of
+    the following:
 
     - disable
     - mouse:button (button is one of 'left', 'right', 'middle', 'back', 'forward')
@@ -81,24 +203,52 @@ impl FromStr for SingleButton {
 
 #[derive(Clap)]
 #[clap(after_help = r"DISCUSSION:
-    The mouse has support for 8 dpi profiles, of which each has a
-    configured dpi value and a color (which is displayed on the LED
-    on the bottom of the mouse. For example, to change the color
-    of dpi profile number 3, you could use
+    The mouse has support for several dpi profiles (8 for the Model O),
+    of which each has a configured dpi value and a color (which
+    is displayed on the LED on the bottom of the mouse. For example, to
+    change the color of dpi profile number 3, you could use
 
         gloryctl dpi -c 00ffff 3
 
-    At this point, it is not possible to enable or disable profiles.")] // TODO
+    The dpi value can also be given separately for the X and Y axes,
+    as in '-d 1600x800'. The dpi button only cycles through the enabled
+    profiles. A new profile can be inserted before an existing one
+    using --insert, which shifts the following profiles. This requires
+    the last one to be disabled.
+
+    If no profile number is given, all the profiles are printed.")]
 struct Dpi {
-    #[clap(possible_values = &["1", "2", "3", "4", "5", "6", "7", "8"])]
-    which: usize,
+    which: Option<usize>,
 
     #[clap(short, long)]
     color: Option<Color>,
 
+    /// Either a single value or XxY
     #[clap(short, long)]
-    dpi: Option<u16>,
-    // TODO independent X and Y
+    dpi: Option<DpiValue>,
+
+    #[clap(long, conflicts_with = "disable")]
+    enable: bool,
+
+    #[clap(long)]
+    disable: bool,
+
+    /// Make the profile the active one
+    #[clap(short, long)]
+    select: bool,
+
+    /// Insert a new profile in the given slot, initially a copy of the
+    /// profile previously there
+    #[clap(long, conflicts_with_all = &["remove", "move-to"])]
+    insert: bool,
+
+    /// Remove the profile, shifting the following ones
+    #[clap(long, conflicts_with_all = &["color", "dpi", "enable", "disable", "select", "move-to"])]
+    remove: bool,
+
+    /// Move the profile to another slot, shifting the ones in between
+    #[clap(long, value_name = "SLOT")]
+    move_to: Option<usize>,
 }
 
 #[derive(Clap)]
@@ -118,15 +268,168 @@ struct Macro {
 }
 
 #[derive(Clap)]
+#[clap(after_help = r"DISCUSSION:
+    A profile is a TOML file containing any of the following settings.
+    Settings which are not present are left unchanged.
+
+        polling_rate = 1000
+        lod = 1
+        buttons = ['mouse:left', 'mouse:right', 'mouse:middle',
+                   'mouse:back', 'mouse:forward', 'dpi:loop']
+
+        [dpi]
+        current = 1
+        profiles = [
+            { enabled = true, value = 800, color = 'ff0000' },
+            { enabled = true, value = [1600, 800], color = '00ff00' },
+        ]
+
+        [rgb]
+        effect = 'breathing'
+        breathing = { speed = 'medium', count = 2, colors = ['ff0000', '0000ff'] }
+
+        [[macro]]
+        bank = 0
+        events = ['down:keyboard:4:50', 'up:keyboard:4:50']
+
+    Button actions and macro events use the same format as the 'button'
+    and 'macro' subcommands. DPI profiles which are not listed are disabled.")]
+struct Apply {
+    file: PathBuf,
+}
+
+#[derive(Clap)]
+#[clap(after_help = r"DISCUSSION:
+    The exported profile can be written to another mouse using 'apply'.
+    The button mapping and macros cannot be read from the mouse, so the
+    ones last written by gloryctl on this computer are exported instead,
+    if known.")]
+struct Export {
+    /// Output file, the profile is printed if not specified
+    file: Option<PathBuf>,
+}
+
+#[derive(Clap)]
+#[clap(after_help = r"DISCUSSION:
+    Unlike 'export', the backup contains the config report octet for
+    octet, including the parts gloryctl does not understand. It can
+    only be restored to a mouse with the same firmware version and sensor.")]
+struct BackupCmd {
+    file: PathBuf,
+}
+
+#[derive(Clap)]
+struct Restore {
+    file: PathBuf,
+
+    /// Restore even if the firmware version or sensor of the mouse differs
+    /// from the one the backup was taken from
+    #[clap(long)]
+    force: bool,
+}
+
+#[derive(Clap)]
+#[clap(after_help = r"DISCUSSION:
+    The lift-off distance is the height at which the mouse stops tracking
+    when lifted. It can be set to 1 (lower) or 2 (higher).
+
+    If no option is given, the current settings are printed.")]
+struct Sensor {
+    /// Polling rate in Hz, one of 125, 250, 500 or 1000
+    #[clap(short, long)]
+    polling_rate: Option<PollingRate>,
+
+    /// Lift-off distance, checked against the range the firmware accepts
+    #[clap(short, long)]
+    lod: Option<u8>,
+}
+
+#[derive(Clap)]
+#[clap(after_help = r"DISCUSSION:
+    The debounce time is how long the mouse ignores a button after it
+    changes state. Increasing it can help with switches which register
+    a single click as a double click. The mouse only supports even values
+    from 2 to 16 ms, odd ones are rounded up.
+
+    If no time is given, the current one is printed.")]
+struct Debounce {
+    /// Debounce time in milliseconds
+    ms: Option<u16>,
+}
+
+#[derive(Clap)]
+#[clap(after_help = r"DISCUSSION:
+    The file contains a single report starting with the report ID, either as
+    raw octets or as hex digits, in which case whitespace is ignored. Reports
+    shorter than 520 octets are padded with zeroes.")]
+enum DecodeCmd {
+    /// Decode a config report
+    Config { file: PathBuf },
+    /// Decode a button map report, printing the mapping of each button
+    Buttonmap { file: PathBuf },
+    /// Decode a macro bank report, printing its events
+    Macro { file: PathBuf },
+}
+
+#[derive(Clap)]
+#[clap(after_help = r"DISCUSSION:
+    The file is a pcap or pcapng capture of usbmon traffic, as saved by
+    Wireshark or tcpdump. The feature reports transferred to and from the
+    control interface are printed together with their decoded contents.
+    Commands, octets and reports which are not understood are marked as
+    unknown.")]
+struct Capture {
+    file: PathBuf,
+}
+
+#[derive(Clap)]
+#[clap(after_help = r"DISCUSSION:
+    The device is given by its USB vendor and product ID in hex, as shown
+    by lsusb, for example 258a:0036. It does not need to be in the device
+    database. Only its firmware version and config are read, nothing is
+    written to it. If it answers like a supported mouse but is missing
+    from the database, an entry to add to it is printed.
+
+    The device database is read from /etc/gloryctl/devices.toml and
+    $XDG_CONFIG_HOME/gloryctl/devices.toml, in addition to the models
+    built into gloryctl.")]
+struct ProbeCmd {
+    /// USB IDs of the device, as VID:PID
+    device: UsbId,
+
+    /// The USB interface receiving the feature reports
+    #[clap(long, default_value = "1")]
+    interface: i32,
+}
+
+struct UsbId {
+    vendor: u16,
+    product: u16,
+}
+
+impl FromStr for UsbId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (vendor, product) = s.split_once(':').context("Format: VID:PID")?;
+        Ok(Self {
+            vendor: u16::from_str_radix(vendor, 16)?,
+            product: u16::from_str_radix(product, 16)?,
+        })
+    }
+}
+
+#[derive(Clap)]
+#[allow(clippy::enum_variant_names)]
 enum Rgb {
     /// Lighting disabled
     Off,
     /// Rotating rainbow (default for new mice)
     Glorious {
-        #[clap(arg_enum, long, short)]
+        #[clap(long, short, possible_values = Direction::NAMES)]
         direction: Option<Direction>,
 
-        #[clap(arg_enum, long, short)]
+        #[clap(long, short, possible_values = Speed::NAMES)]
         speed: Option<Speed>,
     },
     /// Single color
@@ -134,12 +437,12 @@ enum Rgb {
         #[clap(long, short)]
         color: Option<Color>,
 
-        #[clap(arg_enum, long, short)]
+        #[clap(long, short, possible_values = Brightness::NAMES)]
         brightness: Option<Brightness>,
     },
     /// Slowly cycles through the given list of colors
     Breathing {
-        #[clap(arg_enum, long, short)]
+        #[clap(long, short, possible_values = Speed::NAMES)]
         speed: Option<Speed>,
 
         //#[clap(long, short, max_values = 7)]
@@ -148,30 +451,30 @@ enum Rgb {
         #[clap(long, short)]
         colors: Vec<Color>,
     },
-    ///
+    /// Light running along the LED strips
     Tail {
-        #[clap(arg_enum, long, short)]
+        #[clap(long, short, possible_values = Brightness::NAMES)]
         brightness: Option<Brightness>,
 
-        #[clap(arg_enum, long, short)]
+        #[clap(long, short, possible_values = Speed::NAMES)]
         speed: Option<Speed>,
     },
     /// Cycle through colors seamlessly
     SeamlessBreathing {
-        #[clap(arg_enum, long, short)]
+        #[clap(long, short, possible_values = Speed::NAMES)]
         speed: Option<Speed>,
     },
-    /// Constant color for each of the six LEDs
+    /// Constant color for each of the LEDs
     ConstantRgb {
-        #[clap(long, short, number_of_values = 6)]
+        #[clap(long, short)]
         colors: Vec<Color>,
     },
     /// Switching between two configured colors
     Rave {
-        #[clap(arg_enum, long, short)]
+        #[clap(long, short, possible_values = Brightness::NAMES)]
         brightness: Option<Brightness>,
 
-        #[clap(arg_enum, long, short)]
+        #[clap(long, short, possible_values = Speed::NAMES)]
         speed: Option<Speed>,
 
         #[clap(long, short, number_of_values = 2)]
@@ -179,19 +482,19 @@ enum Rgb {
     },
     /// Randomly changing colors
     Random {
-        #[clap(arg_enum, long, short)]
+        #[clap(long, short, possible_values = Speed::NAMES)]
         speed: Option<Speed>,
     },
     Wave {
-        #[clap(arg_enum, long, short)]
+        #[clap(long, short, possible_values = Brightness::NAMES)]
         brightness: Option<Brightness>,
 
-        #[clap(arg_enum, long, short)]
+        #[clap(long, short, possible_values = Speed::NAMES)]
         speed: Option<Speed>,
     },
     /// Single color breathing
     SingleBreathing {
-        #[clap(arg_enum, long, short)]
+        #[clap(long, short, possible_values = Speed::NAMES)]
         speed: Option<Speed>,
 
         #[clap(long, short)]
@@ -199,118 +502,323 @@ enum Rgb {
     },
 }
 
-#[derive(ArgEnum)]
-enum Direction {
-    Up,
-    Down,
-}
-
-#[derive(ArgEnum)]
-enum Speed {
-    Slow,
-    Medium,
-    Fast,
-}
-
-#[derive(ArgEnum)]
-enum Brightness {
-    _0,
-    _25,
-    _50,
-    _75,
-    _100,
+impl Dump {
+    fn run(&self, dev: &mut Device) -> Result<()> {
+        if self.annotate {
+            println!("Firmware version: {}", dev.read_fw_version()?);
+            println!("\nConfig report:");
+            print!(
+                "{}",
+                layout::annotate(layout::config(), &dev.read_config_raw()?)
+            );
+            println!("\nButton map report:");
+            print!(
+                "{}",
+                layout::annotate(layout::buttonmap(), &dev.read_buttonmap_raw()?)
+            );
+            return Ok(());
+        }
+        dbg!(dev.read_fw_version()?);
+        dbg!(dev.read_config()?);
+        //dbg!(dev.read_buttonmap()?);
+        Ok(())
+    }
 }
 
-impl From<&Direction> for u8 {
-    fn from(d: &Direction) -> u8 {
-        match d {
-            Direction::Up => 1,
-            Direction::Down => 0,
+/// Read a report saved as raw octets or hex digits.
+fn read_report(path: &Path) -> Result<DataReport> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let text = std::str::from_utf8(&data)
+        .ok()
+        .map(|s| s.split_whitespace().collect::<String>())
+        .filter(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit()));
+    let octets = match text {
+        Some(text) => {
+            hex::decode(&text).with_context(|| format!("Invalid hex in {}", path.display()))?
         }
+        None => data,
+    };
+    let mut report = [0; 520];
+    if octets.len() > report.len() {
+        return Err(anyhow!(
+            "{}: {} octets do not fit in a report",
+            path.display(),
+            octets.len()
+        ));
     }
+    report[..octets.len()].copy_from_slice(&octets);
+    Ok(report)
 }
 
-impl From<&Speed> for u8 {
-    fn from(s: &Speed) -> u8 {
-        match s {
-            Speed::Slow => 1,
-            Speed::Medium => 2,
-            Speed::Fast => 3,
+impl DecodeCmd {
+    fn run(&self) -> Result<()> {
+        match self {
+            DecodeCmd::Config { file } => {
+                let conf = Config::from_raw(&read_report(file)?)?;
+                println!("{:#?}", conf);
+            }
+            DecodeCmd::Buttonmap { file } => {
+                let map = decode::buttonmap(&read_report(file)?)?;
+                for (i, action) in map.iter().enumerate() {
+                    println!("{}:{}", i + 1, action);
+                }
+            }
+            DecodeCmd::Macro { file } => {
+                let m = decode::macro_bank(&read_report(file)?)?;
+                println!("Bank {}, {} events:", m.bank_number, m.events.len());
+                for ev in &m.events {
+                    println!("{}", ev);
+                }
+            }
         }
+        Ok(())
     }
 }
 
-impl From<&Brightness> for u8 {
-    fn from(b: &Brightness) -> u8 {
-        match b {
-            Brightness::_0 => 0,
-            Brightness::_25 => 1,
-            Brightness::_50 => 2,
-            Brightness::_75 => 3,
-            Brightness::_100 => 4,
+impl Capture {
+    fn run(&self) -> Result<()> {
+        let transfers = gloryctl::read_usbmon(&self.file, &Registry::load()?)?;
+        if transfers.is_empty() {
+            return Err(anyhow!("{}: no feature reports found", self.file.display()));
         }
+        print!("{}", gloryctl::transcript(&transfers));
+        Ok(())
     }
 }
 
-impl Dump {
-    fn run(&self, dev: &mut GloriousDevice) -> Result<()> {
-        dbg!(dev.read_fw_version()?);
-        dbg!(dev.read_config()?);
-        //dbg!(dev.read_buttonmap()?);
+impl ProbeCmd {
+    fn run(&self, dev: &mut Device) -> Result<()> {
+        let UsbId { vendor, product } = self.device;
+        let probe = Probe::run(dev);
+        print!("{}", probe);
+        match Registry::load()?.find(vendor, product) {
+            Some(model) => println!("Known as the {}.", model.name),
+            None if probe.speaks_protocol() => {
+                let model = Model {
+                    name: format!("Unknown mouse {:04x}:{:04x}", vendor, product).into(),
+                    vendor_id: vendor,
+                    product_ids: vec![product].into(),
+                    control_interface: self.interface,
+                    ..Model::default()
+                };
+                println!(
+                    "\nThe device is not in the database. Check the values of this entry\n\
+                     against the mouse before adding it to the database:\n\n{}",
+                    model.database_entry()
+                );
+            }
+            None if probe.config.is_ok() => println!(
+                "The config decodes, but uses octets gloryctl does not know about. It can be\n\
+                 read, but gloryctl refuses to write it."
+            ),
+            None => println!("The device does not answer like a supported mouse."),
+        }
         Ok(())
     }
 }
 
 impl Buttons {
-    fn run(&self, dev: &mut GloriousDevice) -> Result<()> {
-        let mut map = gloryctl::DEFAULT_MAP;
+    fn mapping(&self, model: &Model) -> Result<ButtonMapping> {
+        let mut map = model.default_buttonmap();
         for b in &self.mappings {
-            if b.which < 1 || b.which > 6 {
-                return Err(anyhow!("Invalid button number {}", b.which));
+            if b.which < 1 || b.which > map.len() {
+                return Err(anyhow!(
+                    "Invalid button number {}, the {} has {} buttons",
+                    b.which,
+                    model.name,
+                    map.len()
+                ));
             }
             let i = b.which - 1;
             map[i] = b.action;
         }
-        dev.send_buttonmap(&map)
+        Ok(map)
+    }
+
+    fn run(&self, dev: &mut Device, host: &HostState) -> Result<()> {
+        let map = self.mapping(dev.model())?;
+        dev.send_buttonmap(&map)?;
+        host.remember(&Profile {
+            buttons: Some(map),
+            ..Default::default()
+        })
     }
 }
 
+/// Check that a DPI profile slot given on the command line exists.
+fn check_dpi_slot(model: &Model, slot: usize) -> Result<()> {
+    if slot < 1 || slot > model.dpi_slots {
+        return Err(anyhow!(
+            "Invalid DPI profile {}, the {} has {} profiles",
+            slot,
+            model.name,
+            model.dpi_slots
+        ));
+    }
+    Ok(())
+}
+
 impl Dpi {
-    fn run(&self, dev: &mut GloriousDevice) -> Result<()> {
-        let mut conf = dev.read_config()?;
-        assert!(self.which >= 1 && self.which <= 8);
-        let i = self.which - 1;
-        let prof = &mut conf.dpi_profiles[i];
+    fn run(&self, dev: &mut Device) -> Result<()> {
+        for slot in self.which.iter().chain(&self.move_to) {
+            check_dpi_slot(dev.model(), *slot)?;
+        }
+        let mut conf = match self.which {
+            Some(_) => dev.read_config_checked()?,
+            None => dev.read_config()?,
+        };
+        let mut i = match self.which {
+            Some(which) => which - 1,
+            None => {
+                let slots = dev.model().dpi_slots;
+                for (i, prof) in conf.dpi_profiles.iter().enumerate().take(slots) {
+                    println!(
+                        "{}{} {:>9} {} {}",
+                        i + 1,
+                        if i == conf.dpi_current_profile as usize {
+                            "*"
+                        } else {
+                            " "
+                        },
+                        prof.value,
+                        prof.color,
+                        if prof.enabled { "enabled" } else { "disabled" }
+                    );
+                }
+                return Ok(());
+            }
+        };
+
+        if self.remove {
+            conf.remove_dpi_profile(i)?;
+            return Ok(dev.send_config(&conf)?);
+        }
+        if self.insert {
+            let prof = DpiProfile {
+                enabled: true,
+                ..conf.dpi_profiles[i]
+            };
+            conf.insert_dpi_profile(i, prof)?;
+        }
+        if let Some(to) = self.move_to {
+            conf.move_dpi_profile(i, to - 1)?;
+            i = to - 1;
+        }
 
+        let prof = &mut conf.dpi_profiles[i];
         if let Some(color) = self.color {
             prof.color = color;
         }
-
         if let Some(dpi) = self.dpi {
-            prof.value = DpiValue::Single(dpi)
+            prof.value = dpi;
         }
+        conf.enable_independent_axes_if_needed();
 
-        conf.fixup_dpi_metadata();
-        dev.send_config(&conf)?;
-        Ok(())
+        if self.enable || self.disable {
+            conf.set_dpi_enabled(i, self.enable)?;
+        }
+        if self.select {
+            conf.set_dpi_current(i)?;
+        }
+        Ok(dev.send_config(&conf)?)
     }
 }
 
 impl Macro {
-    fn run(&self, dev: &mut GloriousDevice) -> Result<()> {
-        if self.bank > 3 {
-            return Err(anyhow!(
-                r"Only 2 macro banks are supported for now,
-                TODO find out how many the hardware supports without bricking it"
-            ));
+    fn run(&self, dev: &mut Device, host: &HostState) -> Result<()> {
+        dev.send_macro_bank(self.bank, &self.events)?;
+        host.remember(&Profile {
+            macros: vec![macros::Macro {
+                bank_number: self.bank,
+                events: self.events.clone(),
+            }],
+            ..Default::default()
+        })
+    }
+}
+
+impl Apply {
+    fn run(&self, dev: &mut Device, host: &HostState) -> Result<()> {
+        let text = std::fs::read_to_string(&self.file)
+            .with_context(|| format!("Failed to read {}", self.file.display()))?;
+        let profile = Profile::from_toml(&text)
+            .with_context(|| format!("Failed to parse {}", self.file.display()))?;
+        profile.apply(dev)?;
+        host.remember(&profile)
+    }
+}
+
+impl Export {
+    fn run(&self, dev: &mut Device, host: &HostState) -> Result<()> {
+        let version = dev.read_fw_version()?;
+        let mut profile = Profile::from_config(&dev.read_config()?);
+        profile.merge_host_side(&host.known()?);
+        let text = format!(
+            "# Exported by gloryctl from a mouse with firmware {}\n{}",
+            version,
+            profile.to_toml()?
+        );
+        match &self.file {
+            Some(path) => std::fs::write(path, text)
+                .with_context(|| format!("Failed to write {}", path.display())),
+            None => {
+                print!("{}", text);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl BackupCmd {
+    fn run(&self, dev: &mut Device) -> Result<()> {
+        Ok(Backup::read_from(dev)?.save(&self.file)?)
+    }
+}
+
+impl Restore {
+    fn run(&self, dev: &mut Device) -> Result<()> {
+        Ok(Backup::load(&self.file)?.restore_to(dev, self.force)?)
+    }
+}
+
+impl Sensor {
+    fn run(&self, dev: &mut Device) -> Result<()> {
+        if self.polling_rate.is_none() && self.lod.is_none() {
+            let conf = dev.read_config()?;
+            println!("Sensor ID: {:#04x}", conf.sensor_id);
+            println!("Polling rate: {}", conf.polling_rate);
+            println!("Lift-off distance: {}", conf.lod);
+            return Ok(());
+        }
+        let mut conf = dev.read_config_checked()?;
+        if let Some(rate) = self.polling_rate {
+            conf.polling_rate = rate;
+        }
+        if let Some(lod) = self.lod {
+            conf.set_lod(lod)?;
+        }
+        Ok(dev.send_config(&conf)?)
+    }
+}
+
+impl Debounce {
+    fn run(&self, dev: &mut Device) -> Result<()> {
+        match self.ms {
+            Some(ms) => {
+                let set = dev.set_debounce(ms)?;
+                if set != ms {
+                    println!("Debounce time rounded to {} ms", set);
+                }
+            }
+            None => println!("{} ms", dev.read_debounce()?),
         }
-        dev.send_macro_bank(self.bank, &self.events)
+        Ok(())
     }
 }
 
 impl Rgb {
-    fn run(&self, dev: &mut GloriousDevice) -> Result<()> {
-        let mut conf = dev.read_config()?;
+    fn run(&self, dev: &mut Device) -> Result<()> {
+        let mut conf = dev.read_config_checked()?;
         match self {
             Rgb::Off => {
                 conf.rgb_current_effect = Effect::Off;
@@ -318,10 +826,10 @@ impl Rgb {
             Rgb::Glorious { direction, speed } => {
                 conf.rgb_current_effect = Effect::Glorious;
                 if let Some(dir) = direction {
-                    conf.rgb_effect_parameters.glorious.direction = dir.into();
+                    conf.rgb_effect_parameters.glorious.direction = *dir;
                 }
                 if let Some(spd) = speed {
-                    conf.rgb_effect_parameters.glorious.speed = spd.into();
+                    conf.rgb_effect_parameters.glorious.speed = *spd;
                 }
             }
             Rgb::Single { color, brightness } => {
@@ -330,18 +838,18 @@ impl Rgb {
                     conf.rgb_effect_parameters.single_color.color = *clr;
                 }
                 if let Some(br) = brightness {
-                    conf.rgb_effect_parameters.single_color.brightness = br.into();
+                    conf.rgb_effect_parameters.single_color.brightness = *br;
                 }
             }
             Rgb::Breathing { speed, colors } => {
                 conf.rgb_current_effect = Effect::Breathing;
                 if let Some(spd) = speed {
-                    conf.rgb_effect_parameters.breathing.speed = spd.into();
+                    conf.rgb_effect_parameters.breathing.speed = *spd;
                 }
                 if colors.len() > 7 {
                     return Err(anyhow::Error::msg("At most 7 colors are supported."));
                 }
-                if colors.len() > 0 {
+                if !colors.is_empty() {
                     conf.rgb_effect_parameters.breathing.count = colors.len().try_into()?;
                     for (i, c) in colors.iter().enumerate() {
                         conf.rgb_effect_parameters.breathing.colors[i] = *c;
@@ -351,23 +859,27 @@ impl Rgb {
             Rgb::Tail { speed, brightness } => {
                 conf.rgb_current_effect = Effect::Tail;
                 if let Some(spd) = speed {
-                    conf.rgb_effect_parameters.tail.speed = spd.into();
+                    conf.rgb_effect_parameters.tail.speed = *spd;
                 }
                 if let Some(br) = brightness {
-                    conf.rgb_effect_parameters.tail.brightness = br.into();
+                    conf.rgb_effect_parameters.tail.brightness = *br;
                 }
             }
             Rgb::SeamlessBreathing { speed } => {
                 conf.rgb_current_effect = Effect::SeamlessBreathing;
                 if let Some(spd) = speed {
-                    conf.rgb_effect_parameters.seamless_breathing.speed = spd.into();
+                    conf.rgb_effect_parameters.seamless_breathing.speed = *spd;
                 }
             }
             Rgb::ConstantRgb { colors } => {
                 conf.rgb_current_effect = Effect::ConstantRgb;
-                assert!(colors.len() <= 6);
-                for (i, c) in colors.iter().enumerate() {
-                    conf.rgb_effect_parameters.constant_rgb.colors[i] = *c;
+                let model = dev.model();
+                if colors.len() > model.leds {
+                    return Err(anyhow!("The {} only has {} LEDs.", model.name, model.leds));
+                }
+                let slots = conf.rgb_effect_parameters.constant_rgb.colors.iter_mut();
+                for (slot, c) in slots.zip(colors) {
+                    *slot = *c;
                 }
             }
             Rgb::Rave {
@@ -377,12 +889,23 @@ impl Rgb {
             } => {
                 conf.rgb_current_effect = Effect::Rave;
                 if let Some(br) = brightness {
-                    conf.rgb_effect_parameters.rave.brightness = br.into();
+                    conf.rgb_effect_parameters.rave.brightness = *br;
                 }
                 if let Some(spd) = speed {
-                    conf.rgb_effect_parameters.rave.speed = spd.into();
+                    conf.rgb_effect_parameters.rave.speed = *spd;
+                }
+                let rave = &conf.rgb_effect_parameters.rave;
+                if colors.len() > rave.colors.capacity() {
+                    return Err(gloryctl::Error::Invalid(vec![Problem {
+                        path: "rgb_effect_parameters.rave.colors".to_string(),
+                        reason: format!(
+                            "{} colors given, the effect has {}",
+                            colors.len(),
+                            rave.colors.capacity()
+                        ),
+                    }])
+                    .into());
                 }
-                assert!(colors.len() <= 2);
                 for (i, c) in colors.iter().enumerate() {
                     conf.rgb_effect_parameters.rave.colors[i] = *c;
                 }
@@ -394,49 +917,278 @@ impl Rgb {
                 // as it behaves the same as if 0 is set for the speed of other effects,
                 // that is the effect is extremely fast).
                 // Initialize the value if needed.
-                if conf.rgb_effect_parameters.random.speed == 0 {
-                    conf.rgb_effect_parameters.random.speed = 1;
+                if !conf.rgb_effect_parameters.random.speed.is_valid() {
+                    conf.rgb_effect_parameters.random.speed = Speed::SLOW;
                 }
                 if let Some(spd) = speed {
-                    conf.rgb_effect_parameters.random.speed = spd.into();
+                    conf.rgb_effect_parameters.random.speed = *spd;
                 }
             }
             Rgb::Wave { brightness, speed } => {
                 conf.rgb_current_effect = Effect::Wave;
                 if let Some(br) = brightness {
-                    conf.rgb_effect_parameters.wave.brightness = br.into();
+                    conf.rgb_effect_parameters.wave.brightness = *br;
                 }
                 if let Some(spd) = speed {
-                    conf.rgb_effect_parameters.wave.speed = spd.into();
+                    conf.rgb_effect_parameters.wave.speed = *spd;
                 }
             }
             Rgb::SingleBreathing { speed, color } => {
                 conf.rgb_current_effect = Effect::SingleBreathing;
                 if let Some(spd) = speed {
-                    conf.rgb_effect_parameters.single_breathing.speed = spd.into();
+                    conf.rgb_effect_parameters.single_breathing.speed = *spd;
                 }
                 if let Some(clr) = color {
                     conf.rgb_effect_parameters.single_breathing.color = *clr;
                 }
             }
         };
-        dev.send_config(&conf)
+        Ok(dev.send_config(&conf)?)
+    }
+}
+
+/// Where the host-side knowledge about the mouse is kept, see `Export`.
+struct HostState {
+    path: Option<PathBuf>,
+    /// Read instead of `path` while it does not exist.
+    fallback: Option<PathBuf>,
+}
+
+impl HostState {
+    fn new(path: Option<PathBuf>) -> Self {
+        HostState {
+            path,
+            fallback: None,
+        }
+    }
+
+    /// The default host state of a connected mouse, see --host-state. `shared`
+    /// is the number of connected mice without a serial number.
+    fn of_device(path: Option<&Path>, found: &FoundDevice, shared: usize) -> Self {
+        if let Some(path) = path {
+            return Self::new(Some(path.to_path_buf()));
+        }
+        if found.serial.is_none() && shared > 1 {
+            eprintln!(
+                "Warning: the mouse at {} has no serial number, its button mapping \
+                 and macros are remembered in the same file as for the {} other \
+                 such mice. Use --host-state to keep them apart.",
+                found.path_lossy(),
+                shared - 1
+            );
+        }
+        HostState {
+            path: Profile::host_state_path(found.serial.as_deref()),
+            fallback: found
+                .serial
+                .as_ref()
+                .and_then(|_| Profile::host_state_path(None)),
+        }
+    }
+
+    fn known(&self) -> Result<Profile> {
+        match &self.path {
+            Some(path) => Ok(Profile::load_or(path, self.fallback.as_deref())?),
+            None => Ok(Profile::default()),
+        }
+    }
+
+    fn remember(&self, written: &Profile) -> Result<()> {
+        match &self.path {
+            Some(path) => {
+                let mut known = self.known()?;
+                known.merge_host_side(written);
+                Ok(known.save(path)?)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Number of mice without a serial number, which share a host state.
+fn without_serial<'a>(devices: impl IntoIterator<Item = &'a FoundDevice>) -> usize {
+    devices.into_iter().filter(|d| d.serial.is_none()).count()
+}
+
+fn run(
+    cmd: &DeviceCommand,
+    transport: Box<dyn Transport + '_>,
+    model: Model,
+    host: &HostState,
+    verify: Option<u32>,
+) -> Result<()> {
+    let mut dev = GloriousDevice::new(transport);
+    dev.set_model(model);
+    dev.set_verify(verify);
+    if cmd.uses_settings() {
+        dev.send_msg(0x02, 1)?;
+    }
+
+    match cmd {
+        DeviceCommand::Dump(dump) => dump.run(&mut dev),
+        DeviceCommand::Button(b) => b.run(&mut dev, host),
+        DeviceCommand::Rgb { rgbcmd } => rgbcmd.run(&mut dev),
+        DeviceCommand::Dpi(dpi) => dpi.run(&mut dev),
+        DeviceCommand::Macro(macro_) => macro_.run(&mut dev, host),
+        DeviceCommand::Apply(apply) => apply.run(&mut dev, host),
+        DeviceCommand::Export(export) => export.run(&mut dev, host),
+        DeviceCommand::Backup(backup) => backup.run(&mut dev),
+        DeviceCommand::Restore(restore) => restore.run(&mut dev),
+        DeviceCommand::Sensor(sensor) => sensor.run(&mut dev),
+        DeviceCommand::Debounce(debounce) => debounce.run(&mut dev),
+        DeviceCommand::Probe(probe) => probe.run(&mut dev),
+    }
+}
+
+fn list(hid: &hidapi::HidApi, registry: &Registry) -> Result<()> {
+    let devices = FoundDevice::list(hid, registry);
+    if devices.is_empty() {
+        return Err(gloryctl::Error::DeviceNotFound.into());
+    }
+    for (i, found) in devices.iter().enumerate() {
+        let version = GloriousDevice::open(hid, found).and_then(|dev| dev.read_fw_version());
+        println!(
+            "{}: {}  firmware {}",
+            i + 1,
+            found,
+            version.unwrap_or_else(|e| format!("unknown ({})", e))
+        );
+    }
+    Ok(())
+}
+
+/// Run the command on every connected mouse, carrying on after a failure, and
+/// print the outcome for each one.
+fn run_all(
+    cmd: &DeviceCommand,
+    hid: &hidapi::HidApi,
+    registry: &Registry,
+    host_state: Option<&Path>,
+    verify: Option<u32>,
+) -> Result<()> {
+    let devices: Vec<_> = FoundDevice::list(hid, registry)
+        .into_iter()
+        .map(|found| {
+            let transport = GloriousDevice::open(hid, &found)
+                .map(|dev| Box::new(dev.transport) as Box<dyn Transport>);
+            (found, transport)
+        })
+        .collect();
+    if devices.is_empty() {
+        return Err(gloryctl::Error::DeviceNotFound.into());
+    }
+    let shared = without_serial(devices.iter().map(|(found, _)| found));
+    let summary = gloryctl::run_on_each(devices, |found, transport| {
+        let host = HostState::of_device(host_state, found, shared);
+        run(cmd, transport, found.model.clone(), &host, verify)
+    });
+    print!("\nSummary:\n{}", summary);
+    Ok(summary.check()?)
+}
+
+fn run_recorded(
+    record: Option<&Path>,
+    cmd: &DeviceCommand,
+    transport: Box<dyn Transport + '_>,
+    model: Model,
+    host: &HostState,
+    verify: Option<u32>,
+) -> Result<()> {
+    match record {
+        Some(path) => {
+            let out = File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            let transport = Box::new(Recorder::new(transport, out)?);
+            run(cmd, transport, model, host, verify)
+        }
+        None => run(cmd, transport, model, host, verify),
     }
 }
 
 fn main() -> Result<()> {
     //Dump {}.run()?;
-    let opts = Opts::parse();
+    let Opts {
+        emulate,
+        record,
+        replay,
+        device,
+        all,
+        host_state,
+        verify,
+        verify_retries,
+        cmd,
+    } = Opts::parse();
+    let verify = if verify { Some(verify_retries) } else { None };
 
-    let hid = hidapi::HidApi::new()?;
-    let mut dev = GloriousDevice::open_first(&hid)?;
-    dev.send_msg(0x02, 1)?;
+    if all
+        && !matches!(
+            cmd,
+            Command::Device(
+                DeviceCommand::Apply(_)
+                    | DeviceCommand::Rgb { .. }
+                    | DeviceCommand::Dpi(_)
+                    | DeviceCommand::Button(_)
+                    | DeviceCommand::Macro(_)
+            )
+        )
+    {
+        return Err(anyhow!(
+            "--all only works with apply, rgb, dpi, button and macro."
+        ));
+    }
+
+    let cmd = match cmd {
+        Command::Device(cmd) => cmd,
+        Command::Standalone(StandaloneCommand::List) if emulate.is_some() || replay.is_some() => {
+            return Err(anyhow!("Only connected mice can be listed."));
+        }
+        Command::Standalone(cmd) => return cmd.run(),
+    };
 
-    match opts.cmd {
-        Command::Dump(dump) => dump.run(&mut dev),
-        Command::Button(b) => b.run(&mut dev),
-        Command::Rgb { rgbcmd } => rgbcmd.run(&mut dev),
-        Command::Dpi(dpi) => dpi.run(&mut dev),
-        Command::Macro(macro_) => macro_.run(&mut dev),
+    if let Some(session) = replay {
+        let replay = Replay::load(&session)?;
+        let host = HostState::new(host_state);
+        run(&cmd, Box::new(&replay), Model::default(), &host, verify)?;
+        return Ok(replay.finish()?);
     }
+
+    if let Some(state) = emulate {
+        let mouse = EmulatedMouse::load(&state)?;
+        let host = HostState::new(host_state);
+        let transport = Box::new(&mouse);
+        run_recorded(
+            record.as_deref(),
+            &cmd,
+            transport,
+            Model::default(),
+            &host,
+            verify,
+        )?;
+        return Ok(mouse.save(&state)?);
+    }
+
+    let registry = Registry::load()?;
+    let hid = hidapi::HidApi::new()?;
+    if all {
+        return run_all(&cmd, &hid, &registry, host_state.as_deref(), verify);
+    }
+    let devices = match &cmd {
+        DeviceCommand::Probe(probe) => {
+            let UsbId { vendor, product } = probe.device;
+            FoundDevice::with_id(&hid, vendor, product, probe.interface, &registry)
+        }
+        _ => FoundDevice::list(&hid, &registry),
+    };
+    let found = FoundDevice::select_or_first(&devices, device.as_deref())?;
+    let host = HostState::of_device(host_state.as_deref(), found, without_serial(&devices));
+    let dev = GloriousDevice::open(&hid, found)?;
+    let model = dev.model().clone();
+    run_recorded(
+        record.as_deref(),
+        &cmd,
+        Box::new(dev.transport),
+        model,
+        &host,
+        verify,
+    )
 }
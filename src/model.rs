@@ -0,0 +1,266 @@
+//! The mice known to speak the protocol. They share the report layouts, but
+//! differ in how much of them they use: a model with fewer buttons only reads
+//! the first entries of the button map, for example.
+//!
+//! Besides the built-in models, the SinoWealth controller behind the vendor ID
+//! is used by many rebranded mice, which can be described in a device database
+//! instead of being compiled in, see `Registry::load`.
+
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::device::{
+    buttonmap::{ButtonAction, DEFAULT_MAP},
+    ButtonMapping, Config, ID_VENDOR, MAX_BUTTONS,
+};
+use crate::error::{Error, Result};
+
+/// Deviations of a model from the behaviour of the Model O firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Quirk {
+    /// The firmware needs more time to process a write before the next
+    /// request, see `SLOW_WRITE_DELAY`.
+    SlowWrites,
+    /// The firmware does not implement the debounce command.
+    NoDebounce,
+}
+
+/// A supported mouse model.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Model {
+    pub name: Cow<'static, str>,
+    pub vendor_id: u16,
+    /// USB product IDs, shared by the variants of the model.
+    pub product_ids: Cow<'static, [u16]>,
+    /// The USB interface receiving the feature reports.
+    pub control_interface: i32,
+    /// Number of physical buttons, the length of the button mapping.
+    pub buttons: usize,
+    /// Number of LEDs which can be given a color of their own.
+    pub leds: usize,
+    /// DPI values accepted by the sensor, in multiples of `dpi_step`.
+    pub dpi_range: RangeInclusive<u16>,
+    pub dpi_step: u16,
+    /// Number of DPI profile slots the DPI button cycles through.
+    pub dpi_slots: usize,
+    /// Number of macro banks, numbered from 0.
+    pub macro_banks: u8,
+    #[serde(default)]
+    pub quirks: Cow<'static, [Quirk]>,
+}
+
+/// The built-in models. Only the Model O is included, with the product ID of
+/// the mouse the protocol was reverse-engineered on. Other mice can be
+/// described in a device database once `gloryctl probe` succeeds for them.
+pub static MODELS: &[Model] = &[Model {
+    name: Cow::Borrowed("Glorious Model O"),
+    vendor_id: ID_VENDOR,
+    product_ids: Cow::Borrowed(&[0x0036]),
+    control_interface: 1,
+    buttons: 6,
+    leds: 6,
+    dpi_range: 100..=12000,
+    dpi_step: 100,
+    dpi_slots: 8,
+    macro_banks: 4,
+    quirks: Cow::Borrowed(&[]),
+}];
+
+impl Model {
+    pub fn has_quirk(&self, quirk: Quirk) -> bool {
+        self.quirks.contains(&quirk)
+    }
+
+    /// The mapping the mouse starts with, its extra buttons being disabled.
+    pub fn default_buttonmap(&self) -> ButtonMapping {
+        (0..self.buttons.min(MAX_BUTTONS))
+            .map(|i| {
+                DEFAULT_MAP
+                    .get(i)
+                    .copied()
+                    .unwrap_or(ButtonAction::Disabled)
+            })
+            .collect()
+    }
+
+    /// Check that the values fit in the reports, as a model read from a
+    /// device database may use any. The config report has room for 8 DPI
+    /// profiles and the colors of 6 LEDs.
+    fn check(&self) -> Result<()> {
+        let conf = Config::default();
+        let dpi_slots = conf.dpi_profiles.capacity();
+        let leds = conf.rgb_effect_parameters.constant_rgb.colors.capacity();
+        let problem = if self.product_ids.is_empty() {
+            Some("no product_ids given".to_string())
+        } else if self.buttons > MAX_BUTTONS {
+            Some(format!("buttons must be at most {}", MAX_BUTTONS))
+        } else if self.leds > leds {
+            Some(format!("leds must be at most {}", leds))
+        } else if self.dpi_slots == 0 || self.dpi_slots > dpi_slots {
+            Some(format!("dpi_slots must be between 1 and {}", dpi_slots))
+        } else if self.dpi_range.is_empty()
+            || *self.dpi_range.start() < 100
+            || *self.dpi_range.end() > 25600
+        {
+            Some("dpi_range must be within 100 and 25600".to_string())
+        } else if self.dpi_step == 0 || self.dpi_step % 100 != 0 {
+            Some("dpi_step must be a multiple of 100".to_string())
+        } else {
+            None
+        };
+        match problem {
+            Some(p) => Err(parse_error!("Model '{}': {}", self.name, p)),
+            None => Ok(()),
+        }
+    }
+
+    /// The model as a `[[model]]` table of a device database.
+    pub fn database_entry(&self) -> String {
+        let hex: Vec<_> = self
+            .product_ids
+            .iter()
+            .map(|p| format!("{:#06x}", p))
+            .collect();
+        let quirks: Vec<_> = self
+            .quirks
+            .iter()
+            .map(|q| match q {
+                Quirk::SlowWrites => "\"slow-writes\"",
+                Quirk::NoDebounce => "\"no-debounce\"",
+            })
+            .collect();
+        let mut out = String::new();
+        let _ = writeln!(out, "[[model]]");
+        let _ = writeln!(out, "name = {:?}", self.name);
+        let _ = writeln!(out, "vendor_id = {:#06x}", self.vendor_id);
+        let _ = writeln!(out, "product_ids = [{}]", hex.join(", "));
+        let _ = writeln!(out, "control_interface = {}", self.control_interface);
+        let _ = writeln!(out, "buttons = {}", self.buttons);
+        let _ = writeln!(out, "leds = {}", self.leds);
+        let _ = writeln!(
+            out,
+            "dpi_range = [{}, {}]",
+            self.dpi_range.start(),
+            self.dpi_range.end()
+        );
+        let _ = writeln!(out, "dpi_step = {}", self.dpi_step);
+        let _ = writeln!(out, "dpi_slots = {}", self.dpi_slots);
+        let _ = writeln!(out, "macro_banks = {}", self.macro_banks);
+        let _ = writeln!(out, "quirks = [{}]", quirks.join(", "));
+        out
+    }
+}
+
+/// The Model O, which is assumed when talking to an emulated mouse or
+/// replaying a session, as the model cannot be told from the reports.
+impl Default for Model {
+    fn default() -> Self {
+        MODELS[0].clone()
+    }
+}
+
+/// The contents of a device database file.
+///
+/// ```toml
+/// [[model]]
+/// name = "Some clone"
+/// vendor_id = 0x258a
+/// product_ids = [0x1007]
+/// control_interface = 1
+/// buttons = 6
+/// leds = 6
+/// dpi_range = [100, 12000]
+/// dpi_step = 100
+/// dpi_slots = 8
+/// macro_banks = 4
+/// quirks = ["slow-writes"]
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Database {
+    #[serde(rename = "model", default)]
+    models: Vec<Model>,
+}
+
+/// The models gloryctl knows about: the built-in ones and the ones read from
+/// device databases.
+#[derive(Debug, Clone)]
+pub struct Registry {
+    models: Vec<Model>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry {
+            models: MODELS.to_vec(),
+        }
+    }
+}
+
+impl Registry {
+    /// The locations of the device databases, the system-wide one first:
+    /// /etc/gloryctl/devices.toml and $XDG_CONFIG_HOME/gloryctl/devices.toml.
+    pub fn database_paths() -> Vec<PathBuf> {
+        let user = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".config")))
+            .map(|dir| dir.join("gloryctl").join("devices.toml"));
+        std::iter::once(PathBuf::from("/etc/gloryctl/devices.toml"))
+            .chain(user)
+            .collect()
+    }
+
+    /// The built-in models and the ones in the device databases at
+    /// `database_paths`.
+    pub fn load() -> Result<Self> {
+        let mut registry = Self::default();
+        for path in Self::database_paths() {
+            registry.add_database(&path)?;
+        }
+        Ok(registry)
+    }
+
+    /// Add the models of a device database file, which take precedence over
+    /// the ones known so far for the same USB IDs. A missing file is ignored.
+    pub fn add_database(&mut self, path: &Path) -> Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let text = fs::read_to_string(path).map_err(Error::io(path))?;
+        let db: Database =
+            toml::from_str(&text).map_err(|e| parse_error!("{}: {}", path.display(), e))?;
+        for model in &db.models {
+            model
+                .check()
+                .map_err(|e| parse_error!("{}: {}", path.display(), e))?;
+        }
+        self.models.extend(db.models);
+        Ok(())
+    }
+
+    pub fn models(&self) -> &[Model] {
+        &self.models
+    }
+
+    /// The model with the given USB IDs, if it is supported.
+    pub fn find(&self, vendor: u16, product: u16) -> Option<&Model> {
+        self.models
+            .iter()
+            .rev()
+            .find(|m| m.vendor_id == vendor && m.product_ids.contains(&product))
+    }
+
+    /// Whether the given USB interface of a device is the one of a supported
+    /// model receiving the feature reports.
+    pub fn is_control_interface(&self, vendor: u16, product: u16, interface: i32) -> bool {
+        self.find(vendor, product)
+            .is_some_and(|m| m.control_interface == interface)
+    }
+}
@@ -0,0 +1,77 @@
+//! Checking whether a device speaks the protocol, to find out if a mouse
+//! missing from the device database can be added to it.
+
+use std::fmt;
+
+use crate::device::{Config, GloriousDevice};
+use crate::error::{Problem, Result};
+use crate::transport::Transport;
+use crate::verify;
+
+/// What a device answered to the requests of `Probe::run`.
+pub struct Probe {
+    /// The firmware version, or why it could not be read.
+    pub version: Result<String>,
+    /// The decoded config report, or why it could not be read or decoded.
+    pub config: Result<Config>,
+    /// Octets of the config report which differ from its official encoding,
+    /// see `verify::round_trip`.
+    pub round_trip: Vec<Problem>,
+}
+
+impl Probe {
+    /// Ask the device for its firmware version and config. This only selects
+    /// commands using report 5 and reads the replies, nothing is written to
+    /// the configuration of the device.
+    pub fn run<T: Transport>(dev: &GloriousDevice<T>) -> Probe {
+        let version = dev.read_fw_version();
+        let mut round_trip = Vec::new();
+        let config = dev.read_config_raw().and_then(|raw| {
+            let conf = Config::from_raw(&raw)?;
+            round_trip = verify::round_trip(&raw, &conf.official().to_raw()?);
+            Ok(conf)
+        });
+        Probe {
+            version,
+            config,
+            round_trip,
+        }
+    }
+
+    /// Whether the device answered like a supported mouse, in which case
+    /// gloryctl can safely modify its config.
+    pub fn speaks_protocol(&self) -> bool {
+        self.version.is_ok() && self.config.is_ok() && self.round_trip.is_empty()
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            Ok(v) => writeln!(f, "Firmware version: {}", v)?,
+            Err(e) => writeln!(f, "Firmware version: {}", e)?,
+        }
+        match &self.config {
+            Ok(c) => writeln!(
+                f,
+                "Config: sensor {:#04x}, {}, {} of {} DPI profiles enabled, effect {:?}",
+                c.sensor_id,
+                c.polling_rate,
+                c.dpi_profile_count,
+                c.dpi_profiles.len(),
+                c.rgb_current_effect
+            )?,
+            Err(e) => writeln!(f, "Config: {}", e)?,
+        }
+        if !self.round_trip.is_empty() {
+            writeln!(
+                f,
+                "The config differs from the one the official software writes:"
+            )?;
+            for p in &self.round_trip {
+                writeln!(f, "    {}", p)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,345 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use arrayvec::{Array, ArrayVec};
+use serde::{Deserialize, Serialize};
+
+use crate::device::{
+    macros::Macro, rgb, ButtonMapping, Color, Config, DpiProfile, GloriousDevice, PollingRate,
+};
+use crate::error::{Error, Result};
+use crate::protocol::encode;
+use crate::transport::Transport;
+use crate::validate::validate_buttonmap;
+
+/// A complete or partial description of the mouse configuration, meant to be
+/// stored in a human-editable TOML file. Sections which are missing are left
+/// unchanged when the profile is applied.
+///
+/// ```toml
+/// polling_rate = 1000
+/// lod = 1
+/// buttons = ["mouse:left", "mouse:right", "mouse:middle",
+///            "mouse:back", "mouse:forward", "dpi:loop"]
+///
+/// [dpi]
+/// current = 1
+/// profiles = [
+///     { enabled = true, value = 800, color = "ff0000" },
+///     { enabled = true, value = [1600, 800], color = "00ff00" },
+/// ]
+///
+/// [rgb]
+/// effect = "breathing"
+/// breathing = { speed = "medium", count = 2, colors = ["ff0000", "0000ff"] }
+///
+/// [[macro]]
+/// bank = 0
+/// events = ["down:keyboard:4:50", "up:keyboard:4:50"]
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub polling_rate: Option<PollingRate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lod: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buttons: Option<ButtonMapping>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dpi: Option<DpiSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rgb: Option<RgbSettings>,
+    #[serde(rename = "macro", default, skip_serializing_if = "Vec::is_empty")]
+    pub macros: Vec<Macro>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DpiSettings {
+    /// The active profile, numbered from 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<u8>,
+    /// Profiles which are not listed are disabled.
+    #[serde(default)]
+    pub profiles: ArrayVec<[DpiProfile; 8]>,
+}
+
+/// The selected effect and parameters of any of the effects. Colors which are
+/// not listed for effects using several of them are left unchanged.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RgbSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effect: Option<rgb::Effect>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub glorious: Option<rgb::params::Glorious>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub single_color: Option<rgb::params::SingleColor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub breathing: Option<rgb::params::Breathing>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tail: Option<rgb::params::Tail>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seamless_breathing: Option<rgb::params::SeamlessBreathing>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub constant_rgb: Option<rgb::params::ConstantRgb>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rave: Option<rgb::params::Rave>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub random: Option<rgb::params::Random>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wave: Option<rgb::params::Wave>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub single_breathing: Option<rgb::params::SingleBreathing>,
+}
+
+/// Fill the colors missing from `new` with the ones from `old`, so the
+/// encoded report always contains the full array.
+fn merge_colors<A: Array<Item = Color>>(new: &mut ArrayVec<A>, old: &ArrayVec<A>) {
+    let n = new.len();
+    new.extend(old.iter().skip(n).cloned());
+}
+
+impl RgbSettings {
+    fn apply(&self, conf: &mut Config) {
+        let params = &mut conf.rgb_effect_parameters;
+        if let Some(effect) = self.effect {
+            conf.rgb_current_effect = effect;
+        }
+        if let Some(p) = &self.glorious {
+            params.glorious = p.clone();
+        }
+        if let Some(p) = &self.single_color {
+            params.single_color = p.clone();
+        }
+        if let Some(p) = &self.breathing {
+            let mut colors = p.colors.clone();
+            merge_colors(&mut colors, &params.breathing.colors);
+            params.breathing = rgb::params::Breathing {
+                colors,
+                ..p.clone()
+            };
+        }
+        if let Some(p) = &self.tail {
+            params.tail = p.clone();
+        }
+        if let Some(p) = &self.seamless_breathing {
+            params.seamless_breathing = p.clone();
+        }
+        if let Some(p) = &self.constant_rgb {
+            let mut colors = p.colors.clone();
+            merge_colors(&mut colors, &params.constant_rgb.colors);
+            params.constant_rgb = rgb::params::ConstantRgb { colors };
+        }
+        if let Some(p) = &self.rave {
+            let mut colors = p.colors.clone();
+            merge_colors(&mut colors, &params.rave.colors);
+            params.rave = rgb::params::Rave {
+                colors,
+                ..p.clone()
+            };
+        }
+        if let Some(p) = &self.random {
+            params.random = p.clone();
+        }
+        if let Some(p) = &self.wave {
+            params.wave = p.clone();
+        }
+        if let Some(p) = &self.single_breathing {
+            params.single_breathing = p.clone();
+        }
+    }
+}
+
+impl DpiSettings {
+    fn apply(&self, conf: &mut Config) -> Result<()> {
+        for (i, prof) in conf.dpi_profiles.iter_mut().enumerate() {
+            match self.profiles.get(i) {
+                Some(p) => *prof = *p,
+                None => prof.enabled = false,
+            }
+        }
+        conf.fixup_dpi_metadata();
+        conf.enable_independent_axes_if_needed();
+        match self.current {
+            Some(0) => Err(Error::encode("dpi.current", "profiles are numbered from 1")),
+            Some(current) => conf.set_dpi_current(current as usize - 1),
+            // Without a current profile, keep the active one if it is still
+            // enabled.
+            None => {
+                let current = conf.dpi_current_profile as usize;
+                if !conf.dpi_profiles.get(current).is_some_and(|p| p.enabled) {
+                    conf.select_first_enabled_dpi();
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<&Config> for DpiSettings {
+    fn from(conf: &Config) -> Self {
+        DpiSettings {
+            current: Some(conf.dpi_current_profile + 1),
+            profiles: conf.dpi_profiles.clone(),
+        }
+    }
+}
+
+/// Effects with a level the firmware does not accept, such as the speed of
+/// `Random` which the mouse leaves at 0, are left out, as the profile would not
+/// load again. Applying it keeps them as they are.
+impl From<&Config> for RgbSettings {
+    fn from(conf: &Config) -> Self {
+        let params = conf.rgb_effect_parameters.clone();
+        RgbSettings {
+            effect: Some(conf.rgb_current_effect),
+            glorious: Some(params.glorious)
+                .filter(|p| p.speed.is_valid() && p.direction.is_valid()),
+            single_color: Some(params.single_color).filter(|p| p.brightness.is_valid()),
+            breathing: Some(params.breathing).filter(|p| p.speed.is_valid()),
+            tail: Some(params.tail).filter(|p| p.speed.is_valid() && p.brightness.is_valid()),
+            seamless_breathing: Some(params.seamless_breathing).filter(|p| p.speed.is_valid()),
+            constant_rgb: Some(params.constant_rgb),
+            rave: Some(params.rave).filter(|p| p.speed.is_valid() && p.brightness.is_valid()),
+            random: Some(params.random).filter(|p| p.speed.is_valid()),
+            wave: Some(params.wave).filter(|p| p.speed.is_valid() && p.brightness.is_valid()),
+            single_breathing: Some(params.single_breathing).filter(|p| p.speed.is_valid()),
+        }
+    }
+}
+
+impl Profile {
+    /// A profile containing every setting stored in the config report.
+    pub fn from_config(conf: &Config) -> Self {
+        Profile {
+            polling_rate: Some(conf.polling_rate),
+            lod: Some(conf.lod),
+            dpi: Some(DpiSettings::from(conf)),
+            rgb: Some(RgbSettings::from(conf)),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self> {
+        toml::from_str(s).map_err(|e| Error::Parse(e.to_string()))
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).map_err(|e| Error::encode("profile", e.to_string()))
+    }
+
+    /// Load a profile from a file. A missing file is treated as an empty profile.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(path).map_err(Error::io(path))?;
+        Self::from_toml(&text).map_err(|e| parse_error!("{}: {}", path.display(), e))
+    }
+
+    /// Load the profile at `path`, or the one at `fallback` as long as the
+    /// former does not exist.
+    pub fn load_or(path: &Path, fallback: Option<&Path>) -> Result<Self> {
+        match fallback {
+            Some(fallback) if !path.exists() => Self::load(fallback),
+            _ => Self::load(path),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(Error::io(dir))?;
+        }
+        fs::write(path, self.to_toml()?).map_err(Error::io(path))
+    }
+
+    /// Add the button mapping and macros of `other` to this profile, replacing
+    /// the ones already present.
+    pub fn merge_host_side(&mut self, other: &Profile) {
+        if other.buttons.is_some() {
+            self.buttons = other.buttons.clone();
+        }
+        for m in &other.macros {
+            self.macros.retain(|x| x.bank_number != m.bank_number);
+            self.macros.push(m.clone());
+        }
+        self.macros.sort_by_key(|m| m.bank_number);
+    }
+
+    /// The default location of the file remembering the button mapping and
+    /// macros written to the mouse, as those cannot be read back from it.
+    /// Mice reporting a serial number each get a file of their own, the others
+    /// share known.toml. Until a mouse has a file of its own, it is assumed to
+    /// be the one known.toml was written for, see `load_or`.
+    pub fn host_state_path(serial: Option<&str>) -> Option<PathBuf> {
+        let file = match serial {
+            Some(serial) => {
+                let safe: String = serial
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                    .collect();
+                format!("known-{}.toml", safe)
+            }
+            None => "known.toml".to_string(),
+        };
+        std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".local/state")))
+            .map(|dir| dir.join("gloryctl").join(file))
+    }
+
+    /// Apply the settings in the profile over the given config.
+    pub fn apply_to_config(&self, conf: &mut Config) -> Result<()> {
+        if let Some(rate) = self.polling_rate {
+            conf.polling_rate = rate;
+        }
+        if let Some(lod) = self.lod {
+            conf.set_lod(lod)?;
+        }
+        if let Some(dpi) = &self.dpi {
+            dpi.apply(conf)?;
+        }
+        if let Some(rgb) = &self.rgb {
+            rgb.apply(conf);
+        }
+        Ok(())
+    }
+
+    /// Write the whole profile to the mouse. The config is only written if the
+    /// profile contains any of its settings. Everything is checked before the
+    /// first write, so an invalid profile leaves the mouse unchanged.
+    pub fn apply<T: Transport>(&self, dev: &mut GloriousDevice<T>) -> Result<()> {
+        let conf = if self.polling_rate.is_some()
+            || self.lod.is_some()
+            || self.dpi.is_some()
+            || self.rgb.is_some()
+        {
+            let mut conf = dev.read_config_checked()?;
+            self.apply_to_config(&mut conf)?;
+            conf.validate(dev.model())?;
+            Some(conf)
+        } else {
+            None
+        };
+        if let Some(map) = &self.buttons {
+            validate_buttonmap(map, dev.model())?;
+        }
+        for m in &self.macros {
+            dev.check_macro_bank(m.bank_number)?;
+            encode::macro_bank(m.bank_number, &m.events)?;
+        }
+
+        if let Some(conf) = &conf {
+            dev.send_config(conf)?;
+        }
+        if let Some(map) = &self.buttons {
+            dev.send_buttonmap(map)?;
+        }
+        for m in &self.macros {
+            dev.send_macro_bank(m.bank_number, &m.events)?;
+        }
+        Ok(())
+    }
+}
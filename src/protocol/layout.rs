@@ -0,0 +1,367 @@
+//! The layout of each report, as described in the README. A layout passes the
+//! fields of a report in order to a `Report`, which decodes, encodes or lists
+//! them, so it is the only place where the position of a field is defined.
+//! Offsets include the report ID octet. Effect parameters are named after the
+//! effect, "bs" being the brightness and speed octet.
+
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::iter;
+use std::sync::OnceLock;
+
+use super::report::Report;
+use crate::device::macros::MacroReport;
+use crate::device::{
+    ButtonMapReport, Config, HW_CMD_CONF, HW_CMD_DEBOUNCE, HW_CMD_MACRO, HW_CMD_MAP, HW_CMD_VER,
+    HW_REPORT_MSG, MAX_BUTTONS,
+};
+use crate::error::Result;
+
+/// How the octets of a field are to be interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Octets without a known meaning, or a constant header.
+    Raw,
+    /// A number.
+    Number,
+    /// Two numbers, in the upper and lower nibble. The field name contains
+    /// both names separated by a slash.
+    Nibbles,
+    /// A bit mask.
+    Mask,
+    /// DPI values, each octet being `dpi / 100 - 1`.
+    Dpi,
+    /// Colors in RGB order.
+    ColorsRgb,
+    /// Colors in RBG order, as used by the effects.
+    ColorsRbg,
+    /// The "BS" octet of an effect: brightness in the upper nibble and speed
+    /// in the lower one.
+    BrightnessSpeed,
+    /// The current RGB effect.
+    Effect,
+    /// A button action: its type followed by 3 octets of parameters.
+    ButtonAction,
+    /// Macro events of 3 octets each.
+    MacroEvents,
+    /// ASCII text.
+    Text,
+}
+
+/// A named span of octets in a report.
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub offset: usize,
+    pub len: usize,
+    pub name: &'static str,
+    pub kind: Kind,
+}
+
+use Kind::*;
+
+/// The layout of the config report. The parts which the firmware ignores, such
+/// as the nibbles of the "BS" octet an effect does not use, are kept in
+/// `Config::ignored`.
+pub(crate) fn config_report(r: &mut Report<'_>, c: &mut Config) -> Result<()> {
+    r.raw("header", &mut c.header)?;
+    r.value("sensor_id", Number, &mut c.sensor_id)?;
+    r.nibbles(
+        "dpi_axes_independent/polling_rate",
+        Nibbles,
+        &mut c.dpi_axes_independent,
+        &mut c.polling_rate,
+    )?;
+    r.nibbles(
+        "dpi_current_profile/dpi_profile_count",
+        Nibbles,
+        &mut c.dpi_current_profile,
+        &mut c.dpi_profile_count,
+    )?;
+    r.slots("dpi_profiles", &mut c.dpi_profiles)?;
+    let profiles = &mut c.dpi_profiles;
+    r.inverted_mask(
+        "dpi_enabled_mask",
+        profiles.iter_mut().map(|p| &mut p.enabled),
+    )?;
+    let ignored = &mut c.ignored;
+    r.dpi(
+        "dpi_values",
+        c.dpi_axes_independent,
+        profiles.iter_mut().map(|p| &mut p.value),
+        &mut ignored.dpi_values,
+    )?;
+    r.colors(
+        "dpi_colors",
+        ColorsRgb,
+        profiles.iter_mut().map(|p| &mut p.color),
+    )?;
+    r.value("rgb_current_effect", Effect, &mut c.rgb_current_effect)?;
+
+    let p = &mut c.rgb_effect_parameters;
+    r.nibbles(
+        "glorious.bs",
+        BrightnessSpeed,
+        &mut ignored.glorious_brightness,
+        &mut p.glorious.speed,
+    )?;
+    r.value("glorious.direction", Number, &mut p.glorious.direction)?;
+    let single_color = &mut p.single_color;
+    r.nibbles(
+        "single_color.bs",
+        BrightnessSpeed,
+        &mut single_color.brightness,
+        &mut ignored.single_color_speed,
+    )?;
+    r.colors(
+        "single_color.color",
+        ColorsRbg,
+        iter::once(&mut single_color.color),
+    )?;
+    let breathing = &mut p.breathing;
+    r.nibbles(
+        "breathing.bs",
+        BrightnessSpeed,
+        &mut ignored.breathing_brightness,
+        &mut breathing.speed,
+    )?;
+    r.value("breathing.count", Number, &mut breathing.count)?;
+    r.slots("breathing.colors", &mut breathing.colors)?;
+    r.colors("breathing.colors", ColorsRbg, breathing.colors.iter_mut())?;
+    let tail = &mut p.tail;
+    r.nibbles(
+        "tail.bs",
+        BrightnessSpeed,
+        &mut tail.brightness,
+        &mut tail.speed,
+    )?;
+    let seamless = &mut p.seamless_breathing;
+    r.nibbles(
+        "seamless_breathing.bs",
+        BrightnessSpeed,
+        &mut ignored.seamless_breathing_brightness,
+        &mut seamless.speed,
+    )?;
+    let constant = &mut p.constant_rgb;
+    r.nibbles(
+        "constant_rgb.bs",
+        BrightnessSpeed,
+        &mut ignored.constant_rgb_brightness,
+        &mut ignored.constant_rgb_speed,
+    )?;
+    r.slots("constant_rgb.colors", &mut constant.colors)?;
+    r.colors("constant_rgb.colors", ColorsRbg, constant.colors.iter_mut())?;
+    r.raw("unknown", &mut c.unknown.0)?;
+    let rave = &mut p.rave;
+    r.nibbles(
+        "rave.bs",
+        BrightnessSpeed,
+        &mut rave.brightness,
+        &mut rave.speed,
+    )?;
+    r.slots("rave.colors", &mut rave.colors)?;
+    r.colors("rave.colors", ColorsRbg, rave.colors.iter_mut())?;
+    r.nibbles(
+        "random.bs",
+        BrightnessSpeed,
+        &mut ignored.random_brightness,
+        &mut p.random.speed,
+    )?;
+    let wave = &mut p.wave;
+    r.nibbles(
+        "wave.bs",
+        BrightnessSpeed,
+        &mut wave.brightness,
+        &mut wave.speed,
+    )?;
+    let single_breathing = &mut p.single_breathing;
+    r.nibbles(
+        "single_breathing.bs",
+        BrightnessSpeed,
+        &mut ignored.single_breathing_brightness,
+        &mut single_breathing.speed,
+    )?;
+    r.colors(
+        "single_breathing.color",
+        ColorsRbg,
+        iter::once(&mut single_breathing.color),
+    )?;
+
+    r.value("lod", Number, &mut c.lod)?;
+    r.value("unknown", Raw, &mut c.unknown.1)
+}
+
+const BUTTON_NAMES: [&str; MAX_BUTTONS] = [
+    "button 1",
+    "button 2",
+    "button 3",
+    "button 4",
+    "button 5",
+    "button 6",
+    "button 7",
+    "button 8",
+    "button 9",
+    "button 10",
+    "button 11",
+    "button 12",
+    "button 13",
+    "button 14",
+    "button 15",
+    "button 16",
+    "button 17",
+    "button 18",
+    "button 19",
+    "button 20",
+];
+
+/// The layout of the button map report. It always contains 20 button actions,
+/// the ones after the buttons of the mouse being disabled, as the official
+/// software does.
+pub(crate) fn buttonmap_report(r: &mut Report<'_>, map: &mut ButtonMapReport) -> Result<()> {
+    r.raw("header", &mut map.header)?;
+    let mut count = map.mapping.len() as u8;
+    r.value("button count", Number, &mut count)?;
+    r.value("unknown", Raw, &mut map.unknown)?;
+    r.array(
+        &BUTTON_NAMES,
+        ButtonAction,
+        count,
+        &mut map.mapping,
+        &mut map.slots,
+        crate::device::buttonmap::ButtonAction::Disabled,
+    )
+}
+
+/// The layout of a macro bank report.
+pub(crate) fn macro_report(r: &mut Report<'_>, m: &mut MacroReport) -> Result<()> {
+    r.tag("header", &[0x04, 0x30, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00])?;
+    r.value("bank", Number, &mut m.bank.bank_number)?;
+    // Maybe part of the bank number
+    r.value("unknown", Raw, &mut m.unknown)?;
+    let mut count = u8::try_from(m.bank.events.len()).unwrap_or(u8::MAX);
+    r.value("event count", Number, &mut count)?;
+    r.list("events", MacroEvents, 504, count, &mut m.bank.events)
+}
+
+/// The layout of the reply to the version command.
+pub(crate) fn version_report(r: &mut Report<'_>, version: &mut String) -> Result<()> {
+    r.tag("header", &[HW_REPORT_MSG, HW_CMD_VER])?;
+    r.text("version", 4, version)
+}
+
+/// The fields passed by a layout, which does not depend on the values.
+fn fields<T: Default>(
+    cell: &'static OnceLock<Vec<Field>>,
+    layout: fn(&mut Report<'_>, &mut T) -> Result<()>,
+) -> &'static [Field] {
+    cell.get_or_init(|| {
+        let mut r = Report::describe();
+        layout(&mut r, &mut T::default()).expect("describing a report does not fail");
+        r.fields()
+    })
+}
+
+pub fn config() -> &'static [Field] {
+    static FIELDS: OnceLock<Vec<Field>> = OnceLock::new();
+    fields(&FIELDS, config_report)
+}
+
+pub fn buttonmap() -> &'static [Field] {
+    static FIELDS: OnceLock<Vec<Field>> = OnceLock::new();
+    fields(&FIELDS, buttonmap_report)
+}
+
+pub fn macro_bank() -> &'static [Field] {
+    static FIELDS: OnceLock<Vec<Field>> = OnceLock::new();
+    fields(&FIELDS, macro_report)
+}
+
+pub fn version() -> &'static [Field] {
+    static FIELDS: OnceLock<Vec<Field>> = OnceLock::new();
+    fields(&FIELDS, version_report)
+}
+
+/// Names of the commands selected using report 5.
+pub const COMMANDS: &[(u8, &str)] = &[
+    (HW_CMD_VER, "version"),
+    (HW_CMD_CONF, "config"),
+    (HW_CMD_MAP, "button map"),
+    (HW_CMD_DEBOUNCE, "debounce"),
+    (HW_CMD_MACRO, "macro"),
+];
+
+/// Button action types, the first octet of a button action.
+pub const ACTION_MOUSE: u8 = 0x11;
+pub const ACTION_SCROLL: u8 = 0x12;
+pub const ACTION_KEYBOARD: u8 = 0x21;
+pub const ACTION_MEDIA: u8 = 0x22;
+pub const ACTION_REPEAT: u8 = 0x31;
+pub const ACTION_DPI: u8 = 0x41;
+pub const ACTION_DPI_LOCK: u8 = 0x42;
+pub const ACTION_DISABLE: u8 = 0x50;
+pub const ACTION_MACRO: u8 = 0x70;
+
+/// Names of the button action types, as used by `ButtonAction::from_str`.
+pub const BUTTON_ACTIONS: &[(u8, &str)] = &[
+    (ACTION_MOUSE, "mouse"),
+    (ACTION_SCROLL, "scroll"),
+    (ACTION_KEYBOARD, "keyboard"),
+    (ACTION_MEDIA, "media"),
+    (ACTION_REPEAT, "repeat"),
+    (ACTION_DPI, "dpi"),
+    (ACTION_DPI_LOCK, "dpi-lock"),
+    (ACTION_DISABLE, "disable"),
+    (ACTION_MACRO, "macro"),
+];
+
+/// Macro event types, stored in bits 4 to 6 of the first octet of an event.
+pub const EVENT_MOUSE: u8 = 1;
+pub const EVENT_KEYBOARD: u8 = 5;
+pub const EVENT_MODIFIER: u8 = 6;
+
+/// Names of the macro event types.
+pub const MACRO_EVENTS: &[(u8, &str)] = &[
+    (EVENT_MOUSE, "mouse"),
+    (EVENT_KEYBOARD, "keyboard"),
+    (EVENT_MODIFIER, "modifier"),
+];
+
+/// The name of the field containing the octet at `offset`.
+pub fn field_at(fields: &[Field], offset: usize) -> &'static str {
+    fields
+        .iter()
+        .find(|f| f.offset <= offset && offset < f.offset + f.len)
+        .map_or("end of report", |f| f.name)
+}
+
+/// Octets shown per line by `annotate`, a multiple of 3 to keep colors aligned.
+const ANNOTATE_WIDTH: usize = 12;
+
+/// A hex dump of `report` with each line labelled by the field it belongs to.
+/// Octets after the last field are shown up to the last one which is not zero.
+pub fn annotate(fields: &[Field], report: &[u8]) -> String {
+    let mut out = String::new();
+    let mut line = |offset: usize, octets: &[u8], name: &str| {
+        for (i, chunk) in octets.chunks(ANNOTATE_WIDTH).enumerate() {
+            let hex: Vec<_> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let text = format!(
+                "{:#06x}  {:<w$}  {}",
+                offset + i * ANNOTATE_WIDTH,
+                hex.join(" "),
+                if i == 0 { name } else { "" },
+                w = ANNOTATE_WIDTH * 3 - 1
+            );
+            let _ = writeln!(out, "{}", text.trim_end());
+        }
+    };
+    let mut end = 0;
+    for f in fields {
+        let octets = report.get(f.offset..f.offset + f.len).unwrap_or(&[]);
+        line(f.offset, octets, f.name);
+        end = f.offset + f.len;
+    }
+    let rest = report.get(end..).unwrap_or(&[]);
+    if let Some(last) = rest.iter().rposition(|&b| b != 0) {
+        line(end, &rest[..=last], "unused");
+    }
+    out
+}
@@ -1,2 +1,4 @@
 pub mod decode;
 pub mod encode;
+pub mod layout;
+mod report;
@@ -0,0 +1,660 @@
+//! A report being decoded, encoded or described, driven by the descriptions
+//! in `layout`. Each description walks over the fields of a report in order,
+//! passing the value stored in each of them. Depending on the direction, the
+//! value is read from the octets of the field or written into them, so the
+//! decoder and the encoder cannot disagree on where a field is.
+
+use std::convert::TryFrom;
+use std::str;
+
+use arrayvec::{Array, ArrayVec};
+
+use super::layout::{
+    Field, Kind, ACTION_DISABLE, ACTION_DPI, ACTION_DPI_LOCK, ACTION_KEYBOARD, ACTION_MACRO,
+    ACTION_MEDIA, ACTION_MOUSE, ACTION_REPEAT, ACTION_SCROLL, EVENT_KEYBOARD, EVENT_MODIFIER,
+    EVENT_MOUSE,
+};
+use crate::device::buttonmap::{ButtonAction, DpiSwitch, MacroMode};
+use crate::device::{macros, rgb, Color, DpiValue, MediaButton, PollingRate};
+use crate::error::{Error, Result};
+
+enum Mode<'a> {
+    Decode(&'a [u8]),
+    Encode(&'a mut [u8]),
+    Describe(Vec<Field>),
+}
+
+/// The octets of a field, to read a value from or to write it into.
+enum Octets<'a> {
+    Get(&'a [u8]),
+    Put(&'a mut [u8]),
+}
+
+/// A report and the position of the next field in it.
+pub(crate) struct Report<'a> {
+    mode: Mode<'a>,
+    offset: usize,
+}
+
+/// The error returned by a field when its octets do not hold a valid value,
+/// completed by `Report::field` with the position of the field.
+fn invalid(at: usize) -> Error {
+    Error::Decode {
+        offset: at,
+        field: "",
+    }
+}
+
+/// A value stored in a fixed number of octets. `get` returns `None` for octets
+/// which do not hold a valid value, `put` fails for values which cannot be
+/// stored.
+pub(crate) trait Value: Sized {
+    const LEN: usize;
+
+    fn get(b: &[u8]) -> Option<Self>;
+    fn put(&self, b: &mut [u8]) -> Result<()>;
+}
+
+impl Value for u8 {
+    const LEN: usize = 1;
+
+    fn get(b: &[u8]) -> Option<Self> {
+        Some(b[0])
+    }
+
+    fn put(&self, b: &mut [u8]) -> Result<()> {
+        b[0] = *self;
+        Ok(())
+    }
+}
+
+impl Value for bool {
+    const LEN: usize = 1;
+
+    fn get(b: &[u8]) -> Option<Self> {
+        Some(b[0] != 0)
+    }
+
+    fn put(&self, b: &mut [u8]) -> Result<()> {
+        b[0] = u8::from(*self);
+        Ok(())
+    }
+}
+
+/// Implement `Value` for types stored in a single octet using a pair of
+/// conversions.
+macro_rules! octet_value {
+    ($t:ty, $get:expr, $put:expr) => {
+        impl Value for $t {
+            const LEN: usize = 1;
+
+            fn get(b: &[u8]) -> Option<Self> {
+                $get(b[0])
+            }
+
+            fn put(&self, b: &mut [u8]) -> Result<()> {
+                b[0] = $put(*self);
+                Ok(())
+            }
+        }
+    };
+}
+
+octet_value!(PollingRate, |b| PollingRate::try_from(b).ok(), |v| v as u8);
+octet_value!(rgb::Effect, |b| rgb::Effect::try_from(b).ok(), |v| v as u8);
+octet_value!(
+    rgb::Direction,
+    |b| Some(rgb::Direction::from_raw(b)),
+    rgb::Direction::raw
+);
+octet_value!(
+    rgb::Speed,
+    |b| Some(rgb::Speed::from_raw(b)),
+    rgb::Speed::raw
+);
+octet_value!(
+    rgb::Brightness,
+    |b| Some(rgb::Brightness::from_raw(b)),
+    rgb::Brightness::raw
+);
+
+fn dpi_decode(dpiv: u8) -> u16 {
+    ((dpiv as u16) + 1) * 100
+}
+
+fn dpi_encode(dpi: u16) -> Result<u8> {
+    if !(100..=25600).contains(&dpi) {
+        return Err(Error::encode(
+            "DPI value",
+            format!("{} is not between 100 and 25600", dpi),
+        ));
+    }
+    Ok(((dpi / 100) - 1) as u8)
+}
+
+impl Value for ButtonAction {
+    const LEN: usize = 4;
+
+    fn get(b: &[u8]) -> Option<Self> {
+        Some(match b[0] {
+            ACTION_MOUSE => ButtonAction::MouseButton(TryFrom::try_from(b[1]).ok()?),
+            ACTION_SCROLL => ButtonAction::Scroll(b[1] as i8),
+            ACTION_REPEAT => ButtonAction::RepeatButton {
+                which: TryFrom::try_from(b[1]).ok()?,
+                interval: b[2],
+                count: b[3],
+            },
+            ACTION_DPI => ButtonAction::DpiSwitch(match b[1] {
+                0x00 => DpiSwitch::Cycle,
+                0x01 => DpiSwitch::Up,
+                0x02 => DpiSwitch::Down,
+                _ => return None,
+            }),
+            ACTION_DPI_LOCK => ButtonAction::DpiLock(dpi_decode(b[1])),
+            ACTION_MEDIA => ButtonAction::MediaButton(MediaButton::from_bits(
+                u32::from(b[1]) << 16 | u32::from(b[2]) << 8 | u32::from(b[3]),
+            )?),
+            ACTION_KEYBOARD => ButtonAction::KeyboardShortcut {
+                modifiers: TryFrom::try_from(b[1]).ok()?,
+                key: b[2],
+            },
+            ACTION_DISABLE => ButtonAction::Disabled,
+            ACTION_MACRO => ButtonAction::Macro(
+                b[1],
+                match b[2] {
+                    0x01 => MacroMode::Burst(b[3]),
+                    0x02 => MacroMode::RepeatUntilAnotherPress,
+                    0x04 => MacroMode::RepeatUntilRelease,
+                    _ => return None,
+                },
+            ),
+            _ => return None,
+        })
+    }
+
+    fn put(&self, b: &mut [u8]) -> Result<()> {
+        let octets = match self {
+            ButtonAction::MouseButton(btn) => [ACTION_MOUSE, btn.bits(), 0x00, 0x00],
+            ButtonAction::Scroll(amount) => [ACTION_SCROLL, amount.to_be_bytes()[0], 0x00, 0x00],
+            ButtonAction::RepeatButton {
+                which,
+                interval,
+                count,
+            } => [ACTION_REPEAT, which.bits(), *interval, *count],
+            ButtonAction::DpiSwitch(sw) => [ACTION_DPI, *sw as u8, 0x00, 0x00],
+            ButtonAction::DpiLock(dpi) => [ACTION_DPI_LOCK, dpi_encode(*dpi)?, 0x00, 0x00],
+            ButtonAction::MediaButton(x) => {
+                let bs = x.bits().to_be_bytes();
+                [ACTION_MEDIA, bs[1], bs[2], bs[3]]
+            }
+            ButtonAction::KeyboardShortcut { modifiers, key } => {
+                [ACTION_KEYBOARD, modifiers.bits(), *key, 0x00]
+            }
+            ButtonAction::Disabled => [ACTION_DISABLE, 0x01, 0x00, 0x00],
+            ButtonAction::Macro(bank, mode) => match mode {
+                MacroMode::Burst(c) => [ACTION_MACRO, *bank, 0x01, *c],
+                MacroMode::RepeatUntilRelease => [ACTION_MACRO, *bank, 0x04, 0x01],
+                MacroMode::RepeatUntilAnotherPress => [ACTION_MACRO, *bank, 0x02, 0x01],
+            },
+        };
+        b.copy_from_slice(&octets);
+        Ok(())
+    }
+}
+
+impl Value for macros::Event {
+    const LEN: usize = 3;
+
+    fn get(b: &[u8]) -> Option<Self> {
+        let state = if b[0] & 0x80 == 0 {
+            macros::State::Down
+        } else {
+            macros::State::Up
+        };
+        let evtype = match (b[0] >> 4) & 0x7 {
+            EVENT_MOUSE => macros::EventType::Mouse(TryFrom::try_from(b[2]).ok()?),
+            EVENT_KEYBOARD => macros::EventType::Keyboard(b[2]),
+            EVENT_MODIFIER => macros::EventType::Modifier(TryFrom::try_from(b[2]).ok()?),
+            _ => return None,
+        };
+        Some(Self {
+            state,
+            evtype,
+            duration: u16::from_be_bytes([b[0] & 0xf, b[1]]),
+        })
+    }
+
+    fn put(&self, b: &mut [u8]) -> Result<()> {
+        if self.duration > 0xfff {
+            return Err(Error::encode(
+                "macro event duration",
+                format!("{} ms is longer than the maximum of 4095 ms", self.duration),
+            ));
+        }
+        let state = match self.state {
+            macros::State::Up => 1 << 7,
+            macros::State::Down => 0 << 7,
+        };
+        let (typ, keycode) = match self.evtype {
+            macros::EventType::Keyboard(c) => (EVENT_KEYBOARD, c),
+            macros::EventType::Modifier(c) => (EVENT_MODIFIER, c.bits()),
+            macros::EventType::Mouse(c) => (EVENT_MOUSE, c.bits()),
+        };
+        let duration = self.duration.to_be_bytes();
+        b.copy_from_slice(&[state | typ << 4 | duration[0], duration[1], keycode]);
+        Ok(())
+    }
+}
+
+impl<'a> Report<'a> {
+    pub fn decode(report: &'a [u8]) -> Self {
+        Report {
+            mode: Mode::Decode(report),
+            offset: 0,
+        }
+    }
+
+    /// Encode into `report`, which is expected to be filled with zeroes.
+    pub fn encode(report: &'a mut [u8]) -> Self {
+        Report {
+            mode: Mode::Encode(report),
+            offset: 0,
+        }
+    }
+
+    pub fn describe() -> Self {
+        Report {
+            mode: Mode::Describe(Vec::new()),
+            offset: 0,
+        }
+    }
+
+    /// The fields passed so far, when describing the report.
+    pub fn fields(self) -> Vec<Field> {
+        match self.mode {
+            Mode::Describe(fields) => fields,
+            _ => Vec::new(),
+        }
+    }
+
+    fn decoding(&self) -> bool {
+        !matches!(self.mode, Mode::Encode(_))
+    }
+
+    /// Pass the next `len` octets of the report, named `name`, to `transfer`.
+    fn field(
+        &mut self,
+        name: &'static str,
+        kind: Kind,
+        len: usize,
+        transfer: impl FnOnce(Octets<'_>) -> Result<()>,
+    ) -> Result<()> {
+        let range = self.offset..self.offset + len;
+        let offset = self.offset;
+        self.offset += len;
+        let res = match &mut self.mode {
+            Mode::Decode(report) => match report.get(range) {
+                Some(b) => transfer(Octets::Get(b)),
+                None => {
+                    return Err(Error::Decode {
+                        offset: report.len(),
+                        field: name,
+                    })
+                }
+            },
+            Mode::Encode(report) => match report.get_mut(range) {
+                Some(b) => transfer(Octets::Put(b)),
+                None => {
+                    return Err(Error::encode(
+                        "report",
+                        format!("{} octets do not fit in the report", offset + len),
+                    ))
+                }
+            },
+            Mode::Describe(fields) => {
+                fields.push(Field {
+                    offset,
+                    len,
+                    name,
+                    kind,
+                });
+                Ok(())
+            }
+        };
+        res.map_err(|e| match e {
+            Error::Decode { offset: at, .. } => Error::Decode {
+                offset: offset + at,
+                field: name,
+            },
+            e => e,
+        })
+    }
+
+    /// Octets which are checked when decoding, such as the header of a report.
+    pub fn tag(&mut self, name: &'static str, octets: &[u8]) -> Result<()> {
+        self.field(name, Kind::Raw, octets.len(), |b| match b {
+            Octets::Get(b) => match b.iter().zip(octets).position(|(x, y)| x != y) {
+                Some(i) => Err(invalid(i)),
+                None => Ok(()),
+            },
+            Octets::Put(b) => {
+                b.copy_from_slice(octets);
+                Ok(())
+            }
+        })
+    }
+
+    /// Octets which are always written the same way and ignored when decoding,
+    /// even if the report ends before them.
+    pub fn fill(&mut self, name: &'static str, kind: Kind, octets: &[u8]) -> Result<()> {
+        if let Mode::Decode(_) = self.mode {
+            self.offset += octets.len();
+            return Ok(());
+        }
+        self.field(name, kind, octets.len(), |b| {
+            if let Octets::Put(b) = b {
+                b.copy_from_slice(octets);
+            }
+            Ok(())
+        })
+    }
+
+    /// Fill `slots` to its capacity when decoding, or check that it is full
+    /// when encoding. This does not consume any octets.
+    pub fn slots<A: Array>(&mut self, name: &'static str, slots: &mut ArrayVec<A>) -> Result<()>
+    where
+        A::Item: Default,
+    {
+        if self.decoding() {
+            slots.clear();
+            while !slots.is_full() {
+                slots.push(Default::default());
+            }
+        } else if !slots.is_full() {
+            return Err(Error::encode(
+                name,
+                format!("{} items instead of {}", slots.len(), slots.capacity()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Octets without a known meaning, kept as they are.
+    pub fn raw<A: Array<Item = u8>>(
+        &mut self,
+        name: &'static str,
+        v: &mut ArrayVec<A>,
+    ) -> Result<()> {
+        self.slots(name, v)?;
+        self.field(name, Kind::Raw, A::CAPACITY, |b| {
+            match b {
+                Octets::Get(b) => v.copy_from_slice(b),
+                Octets::Put(b) => b.copy_from_slice(v),
+            }
+            Ok(())
+        })
+    }
+
+    pub fn value<T: Value>(&mut self, name: &'static str, kind: Kind, v: &mut T) -> Result<()> {
+        self.field(name, kind, T::LEN, |b| match b {
+            Octets::Get(b) => {
+                *v = T::get(b).ok_or_else(|| invalid(0))?;
+                Ok(())
+            }
+            Octets::Put(b) => v.put(b),
+        })
+    }
+
+    /// Two values stored in the upper and lower nibble of an octet.
+    pub fn nibbles<H: Value, L: Value>(
+        &mut self,
+        name: &'static str,
+        kind: Kind,
+        high: &mut H,
+        low: &mut L,
+    ) -> Result<()> {
+        self.field(name, kind, 1, |b| match b {
+            Octets::Get(b) => {
+                *high = H::get(&[b[0] >> 4]).ok_or_else(|| invalid(0))?;
+                *low = L::get(&[b[0] & 0xf]).ok_or_else(|| invalid(0))?;
+                Ok(())
+            }
+            Octets::Put(b) => {
+                let (mut h, mut l) = ([0], [0]);
+                high.put(&mut h)?;
+                low.put(&mut l)?;
+                if h[0] > 0xf || l[0] > 0xf {
+                    return Err(Error::encode(name, "value does not fit in 4 bits"));
+                }
+                b[0] = h[0] << 4 | l[0];
+                Ok(())
+            }
+        })
+    }
+
+    /// A bit mask with one bit per flag, starting at the lowest one. The bit
+    /// of a flag is set when the flag is false.
+    pub fn inverted_mask<'b>(
+        &mut self,
+        name: &'static str,
+        flags: impl Iterator<Item = &'b mut bool>,
+    ) -> Result<()> {
+        self.field(name, Kind::Mask, 1, |b| {
+            match b {
+                Octets::Get(b) => {
+                    for (i, flag) in flags.enumerate() {
+                        *flag = b[0] & (1 << i) == 0;
+                    }
+                }
+                Octets::Put(b) => {
+                    b[0] = flags
+                        .enumerate()
+                        .fold(0, |mask, (i, flag)| mask | u8::from(!*flag) << i)
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// DPI values stored in one octet each, or in two octets for the X and Y
+    /// axes if `independent` is set. Without independent axes, the second
+    /// half of the field is kept in `unused`.
+    pub fn dpi<'b>(
+        &mut self,
+        name: &'static str,
+        independent: bool,
+        values: impl ExactSizeIterator<Item = &'b mut DpiValue>,
+        unused: &mut [u8],
+    ) -> Result<()> {
+        self.field(name, Kind::Dpi, values.len() * 2, |b| {
+            match b {
+                Octets::Get(b) if independent => {
+                    for (v, xy) in values.zip(b.chunks(2)) {
+                        *v = if xy[0] == xy[1] {
+                            DpiValue::Single(dpi_decode(xy[0]))
+                        } else {
+                            DpiValue::Double(dpi_decode(xy[0]), dpi_decode(xy[1]))
+                        };
+                    }
+                }
+                Octets::Get(b) => {
+                    let (used, rest) = b.split_at(b.len() / 2);
+                    unused.copy_from_slice(rest);
+                    for (v, x) in values.zip(used) {
+                        *v = DpiValue::Single(dpi_decode(*x));
+                    }
+                }
+                Octets::Put(b) if independent => {
+                    for (v, xy) in values.zip(b.chunks_mut(2)) {
+                        let (x, y) = match *v {
+                            DpiValue::Single(x) => (x, x),
+                            DpiValue::Double(x, y) => (x, y),
+                        };
+                        xy[0] = dpi_encode(x)?;
+                        xy[1] = dpi_encode(y)?;
+                    }
+                }
+                Octets::Put(b) => {
+                    let (used, rest) = b.split_at_mut(b.len() / 2);
+                    rest.copy_from_slice(unused);
+                    for (v, x) in values.zip(used.iter_mut()) {
+                        *x = match *v {
+                            DpiValue::Single(x) => dpi_encode(x)?,
+                            DpiValue::Double(_, _) => {
+                                return Err(Error::encode(
+                                    "dpi_axes_independent",
+                                    "a profile has separate X and Y values, but the axes are not independent",
+                                ))
+                            }
+                        };
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Colors of three octets each, in the order given by `kind`.
+    pub fn colors<'b>(
+        &mut self,
+        name: &'static str,
+        kind: Kind,
+        colors: impl ExactSizeIterator<Item = &'b mut Color>,
+    ) -> Result<()> {
+        let rbg = kind == Kind::ColorsRbg;
+        self.field(name, kind, colors.len() * 3, |b| {
+            match b {
+                Octets::Get(b) => {
+                    for (color, c) in colors.zip(b.chunks(3)) {
+                        *color = if rbg {
+                            Color {
+                                r: c[0],
+                                g: c[2],
+                                b: c[1],
+                            }
+                        } else {
+                            Color {
+                                r: c[0],
+                                g: c[1],
+                                b: c[2],
+                            }
+                        };
+                    }
+                }
+                Octets::Put(b) => {
+                    for (color, c) in colors.zip(b.chunks_mut(3)) {
+                        if rbg {
+                            c.copy_from_slice(&[color.r, color.b, color.g]);
+                        } else {
+                            c.copy_from_slice(&[color.r, color.g, color.b]);
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Up to `len / T::LEN` values, of which `count` are present when
+    /// decoding.
+    pub fn list<T: Value>(
+        &mut self,
+        name: &'static str,
+        kind: Kind,
+        len: usize,
+        count: u8,
+        items: &mut Vec<T>,
+    ) -> Result<()> {
+        let max = len / T::LEN;
+        self.field(name, kind, len, |b| match b {
+            Octets::Get(b) => {
+                if usize::from(count) > max {
+                    return Err(invalid(0));
+                }
+                items.clear();
+                for (i, chunk) in b.chunks(T::LEN).take(count.into()).enumerate() {
+                    items.push(T::get(chunk).ok_or_else(|| invalid(i * T::LEN))?);
+                }
+                Ok(())
+            }
+            Octets::Put(b) => {
+                if items.len() > max {
+                    return Err(Error::encode(
+                        name,
+                        format!("{} items given, the maximum is {}", items.len(), max),
+                    ));
+                }
+                for (item, chunk) in items.iter().zip(b.chunks_mut(T::LEN)) {
+                    item.put(chunk)?;
+                }
+                Ok(())
+            }
+        })
+    }
+
+    /// One field of `T::LEN` octets per name, the first ones holding the
+    /// values, of which `count` are present when decoding. The octets of all
+    /// the fields are kept in `slots` when decoding, from which the remaining
+    /// fields are written back, or as `unused` past its end.
+    pub fn array<A: Array>(
+        &mut self,
+        names: &[&'static str],
+        kind: Kind,
+        count: u8,
+        items: &mut ArrayVec<A>,
+        slots: &mut Vec<u8>,
+        unused: A::Item,
+    ) -> Result<()>
+    where
+        A::Item: Value + Default,
+    {
+        let max = names.len().min(A::CAPACITY);
+        if let Mode::Decode(_) = self.mode {
+            if usize::from(count) > max {
+                return Err(Error::Decode {
+                    offset: self.offset,
+                    field: names.first().copied().unwrap_or(""),
+                });
+            }
+            items.clear();
+            items.extend((0..count).map(|_| Default::default()));
+        } else if items.len() > max {
+            return Err(Error::encode(
+                names.first().copied().unwrap_or(""),
+                format!("{} items given, the maximum is {}", items.len(), max),
+            ));
+        }
+        let len = <A::Item as Value>::LEN;
+        if let Mode::Decode(report) = &self.mode {
+            let end = report.len().min(self.offset + names.len() * len);
+            *slots = report.get(self.offset..end).unwrap_or_default().to_vec();
+        }
+        let mut filler = vec![0; len];
+        unused.put(&mut filler)?;
+        for (i, name) in names.iter().enumerate() {
+            match items.get_mut(i) {
+                Some(item) => self.value(name, kind, item)?,
+                None => {
+                    let kept = slots.get(i * len..(i + 1) * len);
+                    self.fill(name, kind, kept.unwrap_or(&filler))?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// ASCII text of exactly `len` octets.
+    pub fn text(&mut self, name: &'static str, len: usize, s: &mut String) -> Result<()> {
+        self.field(name, Kind::Text, len, |b| match b {
+            Octets::Get(b) => {
+                *s = str::from_utf8(b).map_err(|_| invalid(0))?.to_string();
+                Ok(())
+            }
+            Octets::Put(b) if s.len() == len => {
+                b.copy_from_slice(s.as_bytes());
+                Ok(())
+            }
+            Octets::Put(_) => Err(Error::encode(name, format!("must be {} octets long", len))),
+        })
+    }
+}
@@ -0,0 +1,276 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use hex::FromHex;
+
+use crate::error::{Error, Result};
+use crate::transport::Transport;
+
+/// A single exchange with the device, as stored in a session log.
+///
+/// Each one is stored on its own line prefixed with a timestamp in seconds
+/// relative to the start of the session:
+///
+/// ```text
+/// 0.000 send 6 0502010000
+/// 0.020 delay 20
+/// 0.021 get 4 520 520 04110000000006006406...
+/// ```
+///
+/// `send` is followed by the length of the report and its contents, `get` by
+/// the requested report ID, the buffer length, the number of octets returned
+/// and their contents. Trailing zero octets are not written out. Lines
+/// starting with `#` are comments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Exchange {
+    Send(Vec<u8>),
+    Get {
+        report_id: u8,
+        len: usize,
+        response: Vec<u8>,
+    },
+    Delay(Duration),
+}
+
+fn trimmed_hex(data: &[u8]) -> String {
+    let end = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    hex::encode(&data[..end])
+}
+
+fn padded_from_hex(len: usize, s: Option<&&str>) -> Result<Vec<u8>> {
+    let mut data = match s {
+        Some(s) => Vec::from_hex(s)?,
+        None => Vec::new(),
+    };
+    if data.len() > len {
+        return Err(parse_error!(
+            "{} octets of data for a {} octet report",
+            data.len(),
+            len
+        ));
+    }
+    data.resize(len, 0);
+    Ok(data)
+}
+
+impl std::fmt::Display for Exchange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Exchange::Send(data) => write!(f, "send {} {}", data.len(), trimmed_hex(data)),
+            Exchange::Get {
+                report_id,
+                len,
+                response,
+            } => write!(
+                f,
+                "get {} {} {} {}",
+                report_id,
+                len,
+                response.len(),
+                trimmed_hex(response)
+            ),
+            Exchange::Delay(d) => write!(f, "delay {}", d.as_millis()),
+        }
+    }
+}
+
+impl FromStr for Exchange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match words.as_slice() {
+            ["send", len, rest @ ..] if rest.len() <= 1 => Ok(Exchange::Send(padded_from_hex(
+                usize::from_str(len)?,
+                rest.first(),
+            )?)),
+            ["get", id, len, n, rest @ ..] if rest.len() <= 1 => Ok(Exchange::Get {
+                report_id: u8::from_str(id)?,
+                len: usize::from_str(len)?,
+                response: padded_from_hex(usize::from_str(n)?, rest.first())?,
+            }),
+            ["delay", ms] => Ok(Exchange::Delay(Duration::from_millis(u64::from_str(ms)?))),
+            _ => Err(parse_error!("Invalid exchange '{}'", s)),
+        }
+    }
+}
+
+/// A `Transport` which passes everything through to another one and logs
+/// every exchange to `out` in the format described at `Exchange`.
+pub struct Recorder<T: Transport, W: Write> {
+    inner: T,
+    out: RefCell<W>,
+    start: Instant,
+}
+
+impl<T: Transport, W: Write> Recorder<T, W> {
+    pub fn new(inner: T, mut out: W) -> std::io::Result<Self> {
+        writeln!(out, "# gloryctl session recording")?;
+        Ok(Recorder {
+            inner,
+            out: RefCell::new(out),
+            start: Instant::now(),
+        })
+    }
+
+    fn log(&self, ex: &Exchange) -> Result<()> {
+        let t = self.start.elapsed();
+        let mut out = self.out.borrow_mut();
+        writeln!(out, "{}.{:03} {}", t.as_secs(), t.subsec_millis(), ex)
+            .and_then(|_| out.flush())
+            .map_err(|e| transport_error!("Failed to record the session: {}", e))
+    }
+}
+
+impl<T: Transport, W: Write> Transport for Recorder<T, W> {
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        self.inner.send_feature_report(data)?;
+        self.log(&Exchange::Send(data.to_vec()))
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize> {
+        let report_id = buf.first().copied().unwrap_or(0);
+        let len = buf.len();
+        let n = self.inner.get_feature_report(buf)?;
+        self.log(&Exchange::Get {
+            report_id,
+            len,
+            response: buf[..n].to_vec(),
+        })?;
+        Ok(n)
+    }
+
+    fn delay(&self, duration: Duration) {
+        self.inner.delay(duration);
+        // Logging errors are reported by the next exchange, if any.
+        let _ = self.log(&Exchange::Delay(duration));
+    }
+}
+
+/// A `Transport` which serves the responses of a recorded session, failing
+/// as soon as a request differs from the recorded one.
+pub struct Replay {
+    exchanges: Vec<Exchange>,
+    pos: RefCell<usize>,
+    // Delays cannot fail, so a mismatch is remembered and reported later.
+    error: RefCell<Option<String>>,
+}
+
+impl Replay {
+    pub fn new(exchanges: Vec<Exchange>) -> Self {
+        Replay {
+            exchanges,
+            pos: RefCell::new(0),
+            error: RefCell::new(None),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(Error::io(path))?;
+        let mut exchanges = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // The timestamp is only informative, but has to be there.
+            let ex = match line.split_once(' ') {
+                Some((time, ex)) if f64::from_str(time).is_ok() => ex.parse(),
+                _ => Err(parse_error!("timestamp missing before '{}'", line)),
+            }
+            .map_err(|e| parse_error!("{}:{}: {}", path.display(), i + 1, e))?;
+            exchanges.push(ex);
+        }
+        Ok(Replay::new(exchanges))
+    }
+
+    fn next(&self, got: &Exchange) -> Result<&Exchange> {
+        if let Some(e) = self.error.borrow().as_ref() {
+            return Err(Error::Transport(e.clone()));
+        }
+        let mut pos = self.pos.borrow_mut();
+        let expected = self.exchanges.get(*pos).ok_or_else(|| {
+            transport_error!(
+                "Session mismatch at exchange {}: expected end of session, got '{}'",
+                *pos + 1,
+                got
+            )
+        })?;
+        *pos += 1;
+        Ok(expected)
+    }
+
+    fn mismatch(&self, expected: &Exchange, got: &Exchange) -> Error {
+        transport_error!(
+            "Session mismatch at exchange {}: expected '{}', got '{}'",
+            *self.pos.borrow(),
+            expected,
+            got
+        )
+    }
+
+    /// Check that the whole session was consumed and no mismatch occurred.
+    pub fn finish(&self) -> Result<()> {
+        if let Some(e) = self.error.borrow().as_ref() {
+            return Err(Error::Transport(e.clone()));
+        }
+        let pos = *self.pos.borrow();
+        match self.exchanges.get(pos) {
+            Some(ex) => Err(transport_error!(
+                "Session mismatch at exchange {}: expected '{}', got end of session",
+                pos + 1,
+                ex
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Transport for Replay {
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        let got = Exchange::Send(data.to_vec());
+        let expected = self.next(&got)?;
+        if *expected != got {
+            return Err(self.mismatch(expected, &got));
+        }
+        Ok(())
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize> {
+        let report_id = buf.first().copied().unwrap_or(0);
+        let got = Exchange::Get {
+            report_id,
+            len: buf.len(),
+            response: Vec::new(),
+        };
+        match self.next(&got)? {
+            Exchange::Get {
+                report_id: id,
+                len,
+                response,
+            } if *id == report_id && *len == buf.len() && response.len() <= *len => {
+                buf[..response.len()].copy_from_slice(response);
+                Ok(response.len())
+            }
+            expected => Err(self.mismatch(expected, &got)),
+        }
+    }
+
+    fn delay(&self, duration: Duration) {
+        let got = Exchange::Delay(duration);
+        let result = self.next(&got).and_then(|expected| {
+            if *expected != got {
+                Err(self.mismatch(expected, &got))
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(e) = result {
+            self.error.borrow_mut().get_or_insert(e.to_string());
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use hidapi::HidDevice;
+
+use crate::error::Result;
+
+/// The low-level channel used by `GloriousDevice` to exchange feature reports
+/// with the mouse.
+///
+/// The methods mirror the ones provided by `hidapi::HidDevice`: the first
+/// octet of every buffer is the report ID. When reading, the caller places the
+/// requested report ID into `buf[0]` and the implementation fills in the rest,
+/// returning the number of octets read. Implementations other than the one
+/// for `HidDevice` report their failures as `Error::Transport`.
+pub trait Transport {
+    fn send_feature_report(&self, data: &[u8]) -> Result<()>;
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Wait before issuing the next request. The firmware needs some time to
+    /// process certain writes, backends which do not talk to real hardware are
+    /// free to skip the wait.
+    fn delay(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+impl Transport for HidDevice {
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        HidDevice::send_feature_report(self, data)?;
+        Ok(())
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize> {
+        Ok(HidDevice::get_feature_report(self, buf)?)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        (**self).send_feature_report(data)
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize> {
+        (**self).get_feature_report(buf)
+    }
+
+    fn delay(&self, duration: Duration) {
+        (**self).delay(duration)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for &T {
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        (**self).send_feature_report(data)
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> Result<usize> {
+        (**self).get_feature_report(buf)
+    }
+
+    fn delay(&self, duration: Duration) {
+        (**self).delay(duration)
+    }
+}
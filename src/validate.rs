@@ -0,0 +1,274 @@
+//! Checks run before anything is written to the mouse. The encoder stores most
+//! values in fixed-size octets and nibbles, so out-of-range values would
+//! otherwise be silently truncated and leave the mouse in a confusing state.
+
+use std::ops::RangeInclusive;
+
+use crate::device::{
+    buttonmap::{ButtonAction, MacroMode},
+    rgb::{self, Effect},
+    ButtonMapping, Config, DpiValue, LOD_RANGE,
+};
+use crate::error::{Error, Problem, Result};
+use crate::model::Model;
+
+/// The DPI values the config report can store, in multiples of 100.
+const STORABLE_DPI: RangeInclusive<u16> = 100..=25600;
+
+#[derive(Default)]
+struct Problems(Vec<Problem>);
+
+impl Problems {
+    fn add(&mut self, path: impl Into<String>, reason: impl Into<String>) {
+        self.0.push(Problem {
+            path: path.into(),
+            reason: reason.into(),
+        });
+    }
+
+    fn dpi(&mut self, path: impl Into<String>, dpi: u16, range: &RangeInclusive<u16>, step: u16) {
+        if !range.contains(&dpi) {
+            self.add(
+                path,
+                format!(
+                    "{} DPI is not between {} and {}",
+                    dpi,
+                    range.start(),
+                    range.end()
+                ),
+            );
+        } else if dpi % step != 0 {
+            self.add(path, format!("{} DPI is not a multiple of {}", dpi, step));
+        }
+    }
+
+    fn nibble(&mut self, path: impl Into<String>, value: u8) {
+        if value > 0xf {
+            self.add(path, format!("{} does not fit in 4 bits", value));
+        }
+    }
+
+    fn len(&mut self, path: impl Into<String>, len: usize, expected: usize) {
+        if len != expected {
+            self.add(path, format!("{} items instead of {}", len, expected));
+        }
+    }
+
+    fn speed(&mut self, path: &str, speed: rgb::Speed) {
+        if !speed.is_valid() {
+            self.add(
+                format!("{}.speed", path),
+                format!("invalid speed {}", speed),
+            );
+        }
+    }
+
+    fn brightness(&mut self, path: &str, brightness: rgb::Brightness) {
+        if !brightness.is_valid() {
+            self.add(
+                format!("{}.brightness", path),
+                format!("invalid brightness {}", brightness),
+            );
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Invalid(self.0))
+        }
+    }
+}
+
+impl Config {
+    /// Check that every value can be written to a mouse of the given model as
+    /// it is. All the problems found are reported at once.
+    ///
+    /// Only the parameters of the current RGB effect are checked, as the
+    /// mouse ships with unused effects initialized to invalid values (the
+    /// speed of `Random` is 0, for example).
+    pub fn validate(&self, model: &Model) -> Result<()> {
+        let mut p = Problems::default();
+
+        p.len("header", self.header.len(), 9);
+        p.len("dpi_profiles", self.dpi_profiles.len(), 8);
+        p.len("unknown.0", self.unknown.0.len(), 12);
+
+        for (i, prof) in self.dpi_profiles.iter().enumerate() {
+            let path = format!("dpi_profiles[{}].value", i + 1);
+            // Disabled slots and those beyond the model's are never applied
+            // to the sensor, so their values only need to fit in the report.
+            let (range, step) = if prof.enabled && i < model.dpi_slots {
+                (model.dpi_range.clone(), model.dpi_step)
+            } else {
+                (STORABLE_DPI, 100)
+            };
+            match prof.value {
+                DpiValue::Single(v) => p.dpi(path, v, &range, step),
+                DpiValue::Double(x, y) => {
+                    p.dpi(format!("{}.x", path), x, &range, step);
+                    p.dpi(format!("{}.y", path), y, &range, step);
+                    if !self.dpi_axes_independent {
+                        p.add(path, "separate X and Y values require dpi_axes_independent");
+                    }
+                }
+            }
+        }
+        for (i, prof) in self.dpi_profiles.iter().enumerate().skip(model.dpi_slots) {
+            if prof.enabled {
+                p.add(
+                    format!("dpi_profiles[{}].enabled", i + 1),
+                    format!("the {} only has {} slots", model.name, model.dpi_slots),
+                );
+            }
+        }
+        let enabled = self.dpi_profiles.iter().filter(|d| d.enabled).count();
+        if enabled == 0 {
+            p.add("dpi_profiles", "no profile is enabled");
+        }
+        p.nibble("dpi_profile_count", self.dpi_profile_count);
+        if self.dpi_profile_count == 0 {
+            p.add("dpi_profile_count", "must not be 0");
+        }
+        p.nibble("dpi_current_profile", self.dpi_current_profile);
+        match self.dpi_profiles.get(self.dpi_current_profile as usize) {
+            Some(prof) if !prof.enabled => p.add(
+                "dpi_current_profile",
+                format!("profile {} is disabled", self.dpi_current_profile + 1),
+            ),
+            None => p.add(
+                "dpi_current_profile",
+                format!("there is no profile {}", self.dpi_current_profile + 1),
+            ),
+            Some(_) => {}
+        }
+
+        let params = &self.rgb_effect_parameters;
+        let b = &params.breathing;
+        p.len(
+            "rgb_effect_parameters.breathing.colors",
+            b.colors.len(),
+            b.colors.capacity(),
+        );
+        p.len(
+            "rgb_effect_parameters.constant_rgb.colors",
+            params.constant_rgb.colors.len(),
+            params.constant_rgb.colors.capacity(),
+        );
+        p.len(
+            "rgb_effect_parameters.rave.colors",
+            params.rave.colors.len(),
+            params.rave.colors.capacity(),
+        );
+
+        let path = "rgb_effect_parameters";
+        match self.rgb_current_effect {
+            Effect::Off => {}
+            Effect::Glorious => {
+                p.speed(&format!("{}.glorious", path), params.glorious.speed);
+                if !params.glorious.direction.is_valid() {
+                    p.add(
+                        format!("{}.glorious.direction", path),
+                        format!("invalid direction {}", params.glorious.direction),
+                    );
+                }
+            }
+            Effect::SingleColor => p.brightness(
+                &format!("{}.single_color", path),
+                params.single_color.brightness,
+            ),
+            Effect::Breathing => {
+                p.speed(&format!("{}.breathing", path), b.speed);
+                if b.count == 0 || b.count as usize > b.colors.len() {
+                    p.add(
+                        format!("{}.breathing.count", path),
+                        format!("must be between 1 and {}", b.colors.len()),
+                    );
+                }
+            }
+            Effect::Tail => {
+                p.speed(&format!("{}.tail", path), params.tail.speed);
+                p.brightness(&format!("{}.tail", path), params.tail.brightness);
+            }
+            Effect::SeamlessBreathing => p.speed(
+                &format!("{}.seamless_breathing", path),
+                params.seamless_breathing.speed,
+            ),
+            Effect::ConstantRgb => {}
+            Effect::Rave => {
+                p.speed(&format!("{}.rave", path), params.rave.speed);
+                p.brightness(&format!("{}.rave", path), params.rave.brightness);
+            }
+            Effect::Random => p.speed(&format!("{}.random", path), params.random.speed),
+            Effect::Wave => {
+                p.speed(&format!("{}.wave", path), params.wave.speed);
+                p.brightness(&format!("{}.wave", path), params.wave.brightness);
+            }
+            Effect::SingleBreathing => p.speed(
+                &format!("{}.single_breathing", path),
+                params.single_breathing.speed,
+            ),
+        }
+
+        if !LOD_RANGE.contains(&self.lod) {
+            p.add(
+                "lod",
+                format!(
+                    "must be between {} and {}",
+                    LOD_RANGE.start(),
+                    LOD_RANGE.end()
+                ),
+            );
+        }
+
+        p.finish()
+    }
+}
+
+/// Check that the button mapping has an action for every button of the given
+/// model, which can be written to the mouse as it is. All the problems found
+/// are reported at once.
+pub fn validate_buttonmap(map: &ButtonMapping, model: &Model) -> Result<()> {
+    let mut p = Problems::default();
+    p.len("buttons", map.len(), model.buttons);
+    for (i, action) in map.iter().enumerate() {
+        let path = format!("buttons[{}]", i + 1);
+        match *action {
+            ButtonAction::MouseButton(b) if b.is_empty() => p.add(path, "no mouse button given"),
+            ButtonAction::Scroll(amount) if amount == 0 || amount == i8::MIN => p.add(
+                path,
+                format!(
+                    "scroll amount {} is not between -127 and 127, or is 0",
+                    amount
+                ),
+            ),
+            ButtonAction::RepeatButton { which, count, .. } => {
+                if which.is_empty() {
+                    p.add(path.clone(), "no mouse button given");
+                }
+                if count == 0 {
+                    p.add(path, "repeat count must not be 0");
+                }
+            }
+            ButtonAction::DpiLock(dpi) => p.dpi(path, dpi, &model.dpi_range, model.dpi_step),
+            ButtonAction::MediaButton(b) if b.is_empty() => p.add(path, "no media key given"),
+            ButtonAction::Macro(bank, mode) => {
+                if bank >= model.macro_banks {
+                    p.add(
+                        path.clone(),
+                        format!(
+                            "macro bank {} does not exist, the {} has {} banks",
+                            bank, model.name, model.macro_banks
+                        ),
+                    );
+                }
+                if let MacroMode::Burst(0) = mode {
+                    p.add(path, "macro repeat count must not be 0");
+                }
+            }
+            _ => {}
+        }
+    }
+    p.finish()
+}
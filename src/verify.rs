@@ -0,0 +1,79 @@
+//! Comparison of the values written to the mouse with the ones read back,
+//! used by the verify mode of `GloriousDevice`, and of the config reports
+//! read from the mouse with their re-encoded version.
+
+use serde::Serialize;
+use toml::Value;
+
+use crate::device::{Config, DataReport};
+use crate::error::{Error, Problem, Result};
+use crate::protocol::layout;
+
+/// Octets of the config report which differ between reads and writes.
+const WRITE_ONLY_OFFSETS: [usize; 2] = [3, 6];
+
+fn to_value<S: Serialize>(what: &'static str, s: &S) -> Result<Value> {
+    Value::try_from(s).map_err(|e| Error::encode(what, e.to_string()))
+}
+
+fn diff(path: String, sent: &Value, read: &Value, out: &mut Vec<Problem>) {
+    match (sent, read) {
+        (Value::Table(s), Value::Table(r)) => {
+            for (k, v) in s {
+                let p = if path.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", path, k)
+                };
+                match r.get(k) {
+                    Some(w) => diff(p, v, w, out),
+                    None => out.push(Problem {
+                        path: p,
+                        reason: "missing from the values read back".to_string(),
+                    }),
+                }
+            }
+        }
+        (Value::Array(s), Value::Array(r)) if s.len() == r.len() => {
+            for (i, (v, w)) in s.iter().zip(r).enumerate() {
+                diff(format!("{}[{}]", path, i + 1), v, w, out);
+            }
+        }
+        _ if sent != read => out.push(Problem {
+            path,
+            reason: format!("wrote {}, read back {}", sent, read),
+        }),
+        _ => {}
+    }
+}
+
+/// The fields of `read` which differ from `sent`. The header is skipped, as it
+/// holds the command and write magic rather than settings.
+pub(crate) fn config(sent: &Config, read: &Config) -> Result<Vec<Problem>> {
+    let mut sent = to_value("config", sent)?;
+    let mut read = to_value("config", read)?;
+    for v in [&mut sent, &mut read].iter_mut() {
+        if let Value::Table(t) = v {
+            t.remove("header");
+        }
+    }
+    let mut out = Vec::new();
+    diff(String::new(), &sent, &read, &mut out);
+    Ok(out)
+}
+
+/// The octets of the config report `read` from the mouse which differ in
+/// `encoded`, the report obtained by decoding it and encoding it again with
+/// the parts the firmware ignores as the official software writes them (see
+/// `Config::official`). Only the octets described by `layout::config` are
+/// compared.
+pub(crate) fn round_trip(read: &DataReport, encoded: &DataReport) -> Vec<Problem> {
+    let len = layout::config().last().map_or(0, |f| f.offset + f.len);
+    (0..len)
+        .filter(|i| !WRITE_ONLY_OFFSETS.contains(i) && read[*i] != encoded[*i])
+        .map(|i| Problem {
+            path: format!("{:#04x} ({})", i, layout::field_at(layout::config(), i)),
+            reason: format!("read {:02x}, encoded {:02x}", read[i], encoded[i]),
+        })
+        .collect()
+}
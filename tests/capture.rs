@@ -0,0 +1,214 @@
+//! Builds usbmon captures out of the recorded sessions and checks the
+//! transcript printed for them.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+const MOUSE: (u16, u8) = (3, 7);
+const OTHER: (u16, u8) = (3, 2);
+
+struct Urb {
+    id: u64,
+    submission: bool,
+    device: (u16, u8),
+    setup: [u8; 8],
+    data: Vec<u8>,
+}
+
+/// The control transfers of a session, as pairs of submission and completion.
+fn session_urbs(session: &str, device: (u16, u8), first_id: u64) -> Vec<Urb> {
+    let path = format!("{}/tests/sessions/{}", env!("CARGO_MANIFEST_DIR"), session);
+    let mut urbs = Vec::new();
+    let mut id = first_id;
+    for line in fs::read_to_string(path).unwrap().lines() {
+        let words: Vec<_> = line.split_whitespace().collect();
+        let (set, data) = match words.get(1) {
+            Some(&"send") => (true, hex::decode(words[3]).unwrap()),
+            Some(&"get") => (false, hex::decode(words[5]).unwrap()),
+            _ => continue,
+        };
+        let len = (data.len() as u16).to_le_bytes();
+        let (setup, sent, received) = if set {
+            ([0x21, 0x09, data[0], 3, 1, 0, len[0], len[1]], data, vec![])
+        } else {
+            ([0xa1, 0x01, data[0], 3, 1, 0, len[0], len[1]], vec![], data)
+        };
+        for (submission, data) in [(true, sent), (false, received)] {
+            urbs.push(Urb {
+                id,
+                submission,
+                device,
+                setup,
+                data,
+            });
+        }
+        id += 1;
+    }
+    urbs
+}
+
+/// A GET_DESCRIPTOR request for the device descriptor of the mouse.
+fn descriptor_urbs(id: u64) -> Vec<Urb> {
+    let descriptor = vec![
+        0x12, 0x01, 0x00, 0x02, 0, 0, 0, 0x40, 0x8a, 0x25, 0x36, 0x00, 0, 1, 1, 2, 0, 1,
+    ];
+    let setup = [0x80, 0x06, 0x00, 0x01, 0, 0, 0x12, 0];
+    vec![
+        Urb {
+            id,
+            submission: true,
+            device: MOUSE,
+            setup,
+            data: vec![],
+        },
+        Urb {
+            id,
+            submission: false,
+            device: MOUSE,
+            setup,
+            data: descriptor,
+        },
+    ]
+}
+
+/// The usbmon header and data of a packet, with a 48 or 64 octet header.
+fn usbmon_packet(urb: &Urb, mmapped: bool) -> Vec<u8> {
+    let mut p = Vec::new();
+    p.extend_from_slice(&urb.id.to_le_bytes());
+    p.push(if urb.submission { b'S' } else { b'C' });
+    p.push(2);
+    p.push(if urb.setup[0] & 0x80 != 0 { 0x80 } else { 0 });
+    p.push(urb.device.1);
+    p.extend_from_slice(&urb.device.0.to_le_bytes());
+    p.push(if urb.submission { 0 } else { b'-' });
+    p.push(if urb.data.is_empty() { b'<' } else { 0 });
+    p.extend_from_slice(&[0; 12]);
+    let status: i32 = if urb.submission { -115 } else { 0 };
+    p.extend_from_slice(&status.to_le_bytes());
+    p.extend_from_slice(&(urb.data.len() as u32).to_le_bytes());
+    p.extend_from_slice(&(urb.data.len() as u32).to_le_bytes());
+    p.extend_from_slice(&urb.setup);
+    if mmapped {
+        p.extend_from_slice(&[0; 16]);
+    }
+    p.extend_from_slice(&urb.data);
+    p
+}
+
+fn pcap(urbs: &[Urb]) -> Vec<u8> {
+    let mut f = Vec::new();
+    f.extend_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+    f.extend_from_slice(&[2, 0, 4, 0]);
+    f.extend_from_slice(&[0; 8]);
+    f.extend_from_slice(&65535u32.to_le_bytes());
+    f.extend_from_slice(&189u32.to_le_bytes());
+    for (i, urb) in urbs.iter().enumerate() {
+        let p = usbmon_packet(urb, false);
+        f.extend_from_slice(&1000u32.to_le_bytes());
+        f.extend_from_slice(&(i as u32 * 100).to_le_bytes());
+        f.extend_from_slice(&(p.len() as u32).to_le_bytes());
+        f.extend_from_slice(&(p.len() as u32).to_le_bytes());
+        f.extend_from_slice(&p);
+    }
+    f
+}
+
+fn pcapng_block(f: &mut Vec<u8>, typ: u32, body: &[u8]) {
+    let padded = body.len().div_ceil(4) * 4;
+    let len = (12 + padded) as u32;
+    f.extend_from_slice(&typ.to_be_bytes());
+    f.extend_from_slice(&len.to_be_bytes());
+    f.extend_from_slice(body);
+    f.resize(f.len() + padded - body.len(), 0);
+    f.extend_from_slice(&len.to_be_bytes());
+}
+
+/// A big endian pcapng file, with nanosecond timestamps.
+fn pcapng(urbs: &[Urb]) -> Vec<u8> {
+    let mut f = Vec::new();
+    let mut shb = vec![0x1a, 0x2b, 0x3c, 0x4d, 0, 1, 0, 0];
+    shb.extend_from_slice(&[0xff; 8]);
+    pcapng_block(&mut f, 0x0a0d_0d0a, &shb);
+    let mut idb = vec![0, 220, 0, 0, 0, 0, 0xff, 0xff];
+    idb.extend_from_slice(&[0, 9, 0, 1, 9, 0, 0, 0, 0, 0, 0, 0]);
+    pcapng_block(&mut f, 1, &idb);
+    for (i, urb) in urbs.iter().enumerate() {
+        let mut p = usbmon_packet(urb, true);
+        // The usbmon header is in the byte order of the capture.
+        p[..8].reverse();
+        p[12..14].reverse();
+        for field in (28..40).step_by(4) {
+            p[field..field + 4].reverse();
+        }
+        let ts = 1_000_000_000_000u64 + i as u64 * 100_000;
+        let mut epb = vec![0; 4];
+        epb.extend_from_slice(&((ts >> 32) as u32).to_be_bytes());
+        epb.extend_from_slice(&(ts as u32).to_be_bytes());
+        epb.extend_from_slice(&(p.len() as u32).to_be_bytes());
+        epb.extend_from_slice(&(p.len() as u32).to_be_bytes());
+        epb.extend_from_slice(&p);
+        pcapng_block(&mut f, 6, &epb);
+    }
+    f
+}
+
+fn transcript(name: &str, capture: &[u8]) -> String {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::write(&path, capture).unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_gloryctl"))
+        .arg("capture")
+        .arg(&path)
+        .output()
+        .expect("failed to run gloryctl");
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn pcap_transcript() {
+    let out = transcript("dpi.pcap", &pcap(&session_urbs("dpi.txt", MOUSE, 1)));
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(
+        lines[0], "  0.000100 SET 5 unknown command 0x02: 050201",
+        "{}",
+        out
+    );
+    assert_eq!(lines[1], "  0.000300 SET 5 select config", "{}", out);
+    assert_eq!(lines[2], "  0.000500 GET 4 config", "{}", out);
+    assert!(out.contains("SET 4 write config\n"), "{}", out);
+    assert!(out.contains("    dpi 2  1600 00ffff disabled\n"), "{}", out);
+    assert!(!out.contains("unknown octet"), "{}", out);
+}
+
+#[test]
+fn pcapng_transcript_ignores_other_devices() {
+    let mut urbs = descriptor_urbs(1);
+    urbs.extend(session_urbs("debounce.txt", OTHER, 10));
+    urbs.extend(session_urbs("dump-annotate.txt", MOUSE, 100));
+    let out = transcript("dump.pcapng", &pcapng(&urbs));
+    assert!(!out.contains("debounce"), "{}", out);
+    assert!(out.contains("GET 5 version V103\n"), "{}", out);
+    assert!(
+        out.contains("GET 4 button map\n    1:mouse:left\n"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn pcap_transcript_lists_unknown_octets() {
+    let out = transcript(
+        "lossy.pcap",
+        &pcap(&session_urbs("dpi-lossy.txt", MOUSE, 1)),
+    );
+    assert!(
+        out.contains("    unknown octet 0x36 (glorious.bs): read 31, encoded 41\n"),
+        "{}",
+        out
+    );
+}
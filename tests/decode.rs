@@ -0,0 +1,47 @@
+//! Decodes the saved reports in `tests/reports` without a mouse.
+
+use std::process::Command;
+
+fn decode(what: &str, report: &str) -> String {
+    let path = format!("{}/tests/reports/{}", env!("CARGO_MANIFEST_DIR"), report);
+    let out = Command::new(env!("CARGO_BIN_EXE_gloryctl"))
+        .args(["decode", what])
+        .arg(path)
+        .output()
+        .expect("failed to run gloryctl");
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn config() {
+    let out = decode("config", "config.txt");
+    assert!(out.contains("polling_rate: Hz1000"), "{}", out);
+    assert!(out.contains("lod: 1"), "{}", out);
+}
+
+#[test]
+fn buttonmap() {
+    let out = decode("buttonmap", "buttonmap.bin");
+    assert_eq!(
+        out,
+        "1:mouse:left\n2:mouse:right\n3:mouse:middle\n4:macro:1\n5:mouse:forward\n6:keyboard:ctrl:6\n"
+    );
+}
+
+#[test]
+fn macro_bank() {
+    let out = decode("macro", "macro.txt");
+    assert_eq!(
+        out,
+        "Bank 1, 4 events:\n\
+         down:keyboard:4:50\n\
+         up:keyboard:4:50\n\
+         down:modifier:shift:10\n\
+         up:modifier:shift:10\n"
+    );
+}
@@ -0,0 +1,214 @@
+//! Choosing between several connected mice using --device, or all of them
+//! using --all.
+
+use std::ffi::CString;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use gloryctl::{
+    run_on_each, EmulatedMouse, Error, FoundDevice, GloriousDevice, Model, Profile, Transport,
+    MODELS,
+};
+
+fn found(path: &str, serial: Option<&str>, model: &Model) -> FoundDevice {
+    FoundDevice {
+        path: CString::new(path).unwrap(),
+        serial: serial.map(str::to_owned),
+        vendor_id: model.vendor_id,
+        product_id: model.product_ids[0],
+        model: model.clone(),
+    }
+}
+
+/// A rebranded mouse, as described in a device database.
+fn rebranded() -> Model {
+    Model {
+        name: "Some clone".into(),
+        product_ids: vec![0x1007].into(),
+        ..MODELS[0].clone()
+    }
+}
+
+fn desk_and_travel() -> Vec<FoundDevice> {
+    vec![
+        found("/dev/hidraw2", Some("DESK01"), &MODELS[0]),
+        found("/dev/hidraw5", Some("2"), &rebranded()),
+        found("/dev/hidraw7", None, &MODELS[0]),
+    ]
+}
+
+#[test]
+fn select_by_path_serial_and_index() {
+    let devices = desk_and_travel();
+    let path = |sel| FoundDevice::select(&devices, sel).unwrap().path_lossy();
+    assert_eq!(path("/dev/hidraw5"), "/dev/hidraw5");
+    assert_eq!(path("DESK01"), "/dev/hidraw2");
+    assert_eq!(path("3"), "/dev/hidraw7");
+    // A serial number takes precedence over an index.
+    assert_eq!(path("2"), "/dev/hidraw5");
+    assert_eq!(path("1"), "/dev/hidraw2");
+}
+
+#[test]
+fn unknown_selector_is_rejected() {
+    let devices = desk_and_travel();
+    for sel in &["0", "4", "/dev/hidraw3", "TRAVEL"] {
+        match FoundDevice::select(&devices, sel) {
+            Err(Error::NoSuchDevice { selector }) => assert_eq!(&selector, sel),
+            other => panic!("{}: {:?}", sel, other),
+        }
+    }
+}
+
+#[test]
+fn shared_serial_is_ambiguous() {
+    let devices = vec![
+        found("/dev/hidraw2", Some("SN"), &MODELS[0]),
+        found("/dev/hidraw4", Some("SN"), &MODELS[0]),
+    ];
+    assert!(matches!(
+        FoundDevice::select(&devices, "SN"),
+        Err(Error::AmbiguousDevice { count: 2, .. })
+    ));
+    assert_eq!(
+        FoundDevice::select(&devices, "2").unwrap().path_lossy(),
+        "/dev/hidraw4"
+    );
+}
+
+#[test]
+fn first_device_is_the_default() {
+    let devices = desk_and_travel();
+    let first = FoundDevice::select_or_first(&devices, None).unwrap();
+    assert_eq!(first.serial.as_deref(), Some("DESK01"));
+    assert!(matches!(
+        FoundDevice::select_or_first(&[], None),
+        Err(Error::DeviceNotFound)
+    ));
+}
+
+fn gloryctl(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_gloryctl"))
+        .args(args)
+        .output()
+        .expect("failed to run gloryctl")
+}
+
+#[test]
+fn all_is_limited_to_configuring_commands() {
+    for args in &[&["--all", "dump"][..], &["--all", "export"][..]] {
+        let out = gloryctl(args);
+        assert!(!out.status.success());
+        let err = String::from_utf8_lossy(&out.stderr);
+        assert!(
+            err.contains("--all only works with apply, rgb, dpi, button and macro"),
+            "{}",
+            err
+        );
+    }
+    let out = gloryctl(&["--all", "--device", "1", "dpi", "1"]);
+    assert!(!out.status.success());
+}
+
+/// A mouse which stopped answering.
+struct Unplugged;
+
+impl Transport for Unplugged {
+    fn send_feature_report(&self, _data: &[u8]) -> gloryctl::Result<()> {
+        Err(Error::Transport("device disconnected".to_string()))
+    }
+
+    fn get_feature_report(&self, _buf: &mut [u8]) -> gloryctl::Result<usize> {
+        Err(Error::Transport("device disconnected".to_string()))
+    }
+}
+
+#[test]
+fn all_carries_on_after_a_failure() {
+    let (first, last) = (EmulatedMouse::new(), EmulatedMouse::new());
+    let devices: Vec<(FoundDevice, gloryctl::Result<Box<dyn Transport>>)> = vec![
+        (
+            found("/dev/hidraw2", None, &MODELS[0]),
+            Ok(Box::new(&first)),
+        ),
+        (
+            found("/dev/hidraw4", Some("SN4"), &MODELS[0]),
+            Ok(Box::new(Unplugged)),
+        ),
+        (found("/dev/hidraw6", None, &MODELS[0]), Ok(Box::new(&last))),
+    ];
+    let summary = run_on_each(devices, |found, transport| {
+        let mut dev = GloriousDevice::new(transport);
+        dev.set_model(found.model.clone());
+        let mut conf = dev.read_config_checked()?;
+        conf.lod = 2;
+        dev.send_config(&conf)
+    });
+
+    assert_eq!(first.config_raw()[0x81], 2);
+    assert_eq!(last.config_raw()[0x81], 2);
+    assert_eq!(
+        summary.to_string(),
+        "1: /dev/hidraw2  Glorious Model O  serial -  ok\n\
+         2: /dev/hidraw4  Glorious Model O  serial SN4  failed: device disconnected\n\
+         3: /dev/hidraw6  Glorious Model O  serial -  ok\n"
+    );
+    assert_eq!(summary.failed(), 1);
+    assert_eq!(
+        summary.check().unwrap_err().to_string(),
+        "Failed on 1 of 3 mice."
+    );
+}
+
+#[test]
+fn all_counts_devices_which_cannot_be_opened() {
+    let mouse = EmulatedMouse::new();
+    let devices: Vec<(FoundDevice, gloryctl::Result<Box<dyn Transport>>)> = vec![
+        (
+            found("/dev/hidraw2", None, &MODELS[0]),
+            Err(Error::PermissionDenied {
+                path: "/dev/hidraw2".to_string(),
+            }),
+        ),
+        (
+            found("/dev/hidraw3", None, &rebranded()),
+            Ok(Box::new(&mouse)),
+        ),
+    ];
+    let mut ran = 0;
+    let summary = run_on_each(devices, |_, _| {
+        ran += 1;
+        Ok::<_, Error>(())
+    });
+    assert_eq!(ran, 1);
+    let text = summary.to_string();
+    assert!(text.starts_with("1: /dev/hidraw2  Glorious Model O  serial -  failed: Permission denied opening /dev/hidraw2"), "{}", text);
+    assert!(
+        text.ends_with("2: /dev/hidraw3  Some clone  serial -  ok\n"),
+        "{}",
+        text
+    );
+    assert!(summary.check().is_err());
+}
+
+#[test]
+fn host_state_of_a_mouse_starts_from_the_shared_one() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("host-state");
+    let _ = fs::remove_dir_all(&dir);
+    let (shared, own) = (dir.join("known.toml"), dir.join("known-SN4.toml"));
+    let mut written = Profile::from_toml("buttons = ['disable']").unwrap();
+    written.save(&shared).unwrap();
+
+    let known = Profile::load_or(&own, Some(&shared)).unwrap();
+    assert_eq!(known.buttons, written.buttons);
+
+    written.buttons = Profile::from_toml("buttons = ['dpi:up']").unwrap().buttons;
+    written.save(&own).unwrap();
+    let known = Profile::load_or(&own, Some(&shared)).unwrap();
+    assert_eq!(known.buttons, written.buttons);
+    assert_eq!(
+        Profile::load_or(&own, None).unwrap().buttons,
+        written.buttons
+    );
+}
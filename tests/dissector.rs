@@ -0,0 +1,40 @@
+//! Checks that the dissector in `contrib` matches the report layouts.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::process::Command;
+
+/// Lua compilers to check the syntax of the dissector with, the first one
+/// installed being used.
+const LUAC: &[&str] = &["luac", "luac5.4", "luac5.3", "luac5.2"];
+
+#[test]
+fn contrib_dissector_is_up_to_date() {
+    let path = format!("{}/contrib/gloryctl.lua", env!("CARGO_MANIFEST_DIR"));
+    let out = Command::new(env!("CARGO_BIN_EXE_gloryctl"))
+        .arg("dissector")
+        .output()
+        .expect("failed to run gloryctl");
+    assert!(out.status.success());
+    assert!(
+        String::from_utf8(out.stdout).unwrap() == fs::read_to_string(&path).unwrap(),
+        "contrib/gloryctl.lua is out of date, regenerate it with \
+         `cargo run -- dissector > contrib/gloryctl.lua`"
+    );
+
+    for luac in LUAC {
+        match Command::new(luac).arg("-p").arg(&path).output() {
+            Ok(out) => {
+                assert!(
+                    out.status.success(),
+                    "contrib/gloryctl.lua is not valid Lua: {}",
+                    String::from_utf8_lossy(&out.stderr)
+                );
+                return;
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => panic!("failed to run {}: {}", luac, e),
+        }
+    }
+    eprintln!("luac is not installed, skipping the syntax check of contrib/gloryctl.lua");
+}
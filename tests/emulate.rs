@@ -0,0 +1,493 @@
+//! Runs the command line interface against the emulated mouse, and checks the
+//! state it saves after each command.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use gloryctl::{Config, DataReport, EmulatedMouse, GloriousDevice, Profile, Transport};
+
+/// A fresh state file for the emulated mouse, named after the test.
+fn state(test: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join("emulate")
+        .join(test);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.join("state")
+}
+
+fn gloryctl(state: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_gloryctl"))
+        .arg("--emulate")
+        .arg(state)
+        .args(args)
+        .output()
+        .expect("failed to run gloryctl")
+}
+
+fn run_ok(state: &Path, args: &[&str]) -> String {
+    let out = gloryctl(state, args);
+    assert!(
+        out.status.success(),
+        "{:?}: {}",
+        args,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+fn config(state: &Path) -> DataReport {
+    EmulatedMouse::load(state).unwrap().config_raw()
+}
+
+/// The line of the saved state starting with `key`.
+fn state_line(state: &Path, key: &str) -> Option<String> {
+    fs::read_to_string(state)
+        .unwrap()
+        .lines()
+        .find(|l| l.starts_with(key))
+        .map(str::to_owned)
+}
+
+#[test]
+fn dpi() {
+    let state = state("dpi");
+    run_ok(&state, &["dpi", "-d", "1600", "-c", "00ffff", "2"]);
+    let conf = config(&state);
+    assert_eq!(conf[0x0d..0x0f], [0x04, 0x0f]);
+    assert_eq!(conf[0x20..0x23], [0x00, 0xff, 0xff]);
+    let out = run_ok(&state, &["dpi"]);
+    assert!(out.contains("\n2  1600 00ffff disabled\n"), "{}", out);
+}
+
+#[test]
+fn dpi_axes_and_active_profile_are_kept() {
+    let state = state("dpi-kept");
+    run_ok(&state, &["dpi", "-d", "800x800", "--enable", "-s", "2"]);
+    let conf = config(&state);
+    assert_eq!(conf[0x0a] & 0x10, 0x10, "independent axes");
+    assert_eq!(conf[0x0f..0x11], [0x07, 0x07]);
+    let conf = Config::from_raw(&conf).unwrap();
+    assert_eq!(conf.dpi_current_profile, 1);
+
+    run_ok(&state, &["dpi", "--disable", "3"]);
+    let conf = Config::from_raw(&config(&state)).unwrap();
+    assert!(conf.dpi_axes_independent);
+    assert_eq!(conf.dpi_current_profile, 1);
+
+    // Disabling the active profile makes the first enabled one active.
+    run_ok(&state, &["dpi", "--disable", "2"]);
+    let conf = Config::from_raw(&config(&state)).unwrap();
+    assert_eq!(conf.dpi_profile_count, 2);
+    assert_eq!(conf.dpi_current_profile, 0);
+}
+
+#[test]
+fn rgb() {
+    let state = state("rgb");
+    run_ok(&state, &["rgb", "single", "-c", "00ff00", "-b", "50"]);
+    let conf = config(&state);
+    assert_eq!(conf[0x35], 2, "effect");
+    assert_eq!(conf[0x38], 0x20, "brightness");
+    // Effect colors are stored in RBG order.
+    assert_eq!(conf[0x39..0x3c], [0x00, 0x00, 0xff]);
+}
+
+#[test]
+fn sensor() {
+    let state = state("sensor");
+    run_ok(&state, &["sensor", "-p", "500", "-l", "2"]);
+    let conf = config(&state);
+    assert_eq!(conf[0x0a], 0x03, "polling rate");
+    assert_eq!(conf[0x81], 2, "lift-off distance");
+    let out = run_ok(&state, &["sensor"]);
+    assert!(out.contains("Polling rate: 500 Hz"), "{}", out);
+}
+
+#[test]
+fn lod_out_of_range_is_rejected() {
+    let state = state("sensor-lod");
+    let out = gloryctl(&state, &["sensor", "-l", "3"]);
+    assert!(!out.status.success());
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        err.contains("Invalid lod: must be between 1 and 2"),
+        "{}",
+        err
+    );
+    assert!(!state.exists());
+}
+
+#[test]
+fn debounce() {
+    let state = state("debounce");
+    run_ok(&state, &["debounce", "8"]);
+    assert_eq!(state_line(&state, "debounce").unwrap(), "debounce 4");
+    assert_eq!(run_ok(&state, &["debounce"]), "8 ms\n");
+}
+
+#[test]
+fn button() {
+    let state = state("button");
+    run_ok(&state, &["button", "6:mouse:left", "1:disable"]);
+    let line = state_line(&state, "buttonmap ").expect("no button map saved");
+    let map = EmulatedMouse::load(&state)
+        .unwrap()
+        .buttonmap_raw()
+        .unwrap();
+    assert_eq!(line, format!("buttonmap {}", hex::encode(&map[..])));
+    assert_eq!(map[6], 6, "button count");
+    assert_eq!(map[0x08..0x0c], [0x50, 0x01, 0x00, 0x00]);
+    assert_eq!(map[0x0c..0x10], [0x11, 0x02, 0x00, 0x00]);
+    assert_eq!(map[0x1c..0x20], [0x11, 0x01, 0x00, 0x00]);
+}
+
+#[test]
+fn macro_bank() {
+    let state = state("macro");
+    run_ok(
+        &state,
+        &["macro", "2", "down:keyboard:4:50", "up:keyboard:4:50"],
+    );
+    assert!(state_line(&state, "macro 2 ").is_some());
+    assert!(state_line(&state, "macro 0 ").is_none());
+    let bank = EmulatedMouse::load(&state)
+        .unwrap()
+        .macro_bank_raw(2)
+        .unwrap();
+    assert_eq!(bank[..8], [0x04, 0x30, 0x02, 0, 0, 0, 0, 0]);
+    assert_eq!(bank[8], 2, "bank");
+    assert_eq!(bank[10], 2, "event count");
+    assert_eq!(bank[11..17], [0x50, 0x32, 0x04, 0xd0, 0x32, 0x04]);
+}
+
+#[test]
+fn apply_and_export() {
+    let state = state("apply");
+    let profile = state.with_file_name("profile.toml");
+    fs::write(&profile, "polling_rate = 250\nlod = 2\n").unwrap();
+    run_ok(&state, &["apply", profile.to_str().unwrap()]);
+    let conf = config(&state);
+    assert_eq!(conf[0x0a], 0x02);
+    assert_eq!(conf[0x81], 2);
+    let out = run_ok(&state, &["export"]);
+    assert!(out.contains("polling_rate = 250\nlod = 2\n"), "{}", out);
+}
+
+#[test]
+fn backup_and_restore() {
+    let state = state("backup");
+    let backup = state.with_file_name("backup.toml");
+    let original = config(&state);
+    run_ok(&state, &["backup", backup.to_str().unwrap()]);
+    run_ok(&state, &["dpi", "-d", "3200", "1"]);
+    assert_ne!(config(&state)[..], original[..]);
+    run_ok(&state, &["restore", backup.to_str().unwrap()]);
+    assert_eq!(config(&state)[..], original[..]);
+}
+
+#[test]
+fn config_write_without_magic_is_rejected() {
+    let mouse = EmulatedMouse::new();
+    let mut report = mouse.config_raw();
+    report[0x81] = 2;
+    assert_eq!(report[3], 0);
+    assert!(mouse.send_feature_report(&report).is_err());
+    assert_eq!(mouse.config_raw()[0x81], 1);
+
+    report[1] = 0x12;
+    assert!(mouse.send_feature_report(&report).is_err());
+    assert_eq!(mouse.buttonmap_raw(), None);
+}
+
+#[test]
+fn short_data_report_is_rejected() {
+    let mouse = EmulatedMouse::new();
+    let report = [0x04, 0x11, 0x00, 0x7b, 0, 0, 0, 0, 0, 0];
+    match mouse.send_feature_report(&report) {
+        Err(e) => assert!(e.to_string().contains("too short (10 octets)"), "{}", e),
+        Ok(()) => panic!("short report accepted"),
+    }
+    assert_eq!(mouse.config_raw(), EmulatedMouse::new().config_raw());
+}
+
+const PROFILE: &str = r#"
+polling_rate = 500
+lod = 2
+buttons = ['mouse:right', 'mouse:left', 'mouse:middle', 'mouse:back', 'mouse:forward', 'macro:1']
+
+[dpi]
+current = 2
+profiles = [
+    { enabled = true, value = 800, color = 'ff0000' },
+    { enabled = true, value = [1600, 800], color = '00ff00' },
+]
+
+[rgb]
+effect = 'breathing'
+breathing = { speed = 'fast', count = 2, colors = ['ff0000', '0000ff'] }
+
+[[macro]]
+bank = 1
+events = ['down:keyboard:4:50', 'up:keyboard:4:50']
+"#;
+
+#[test]
+fn apply_writes_the_whole_profile() {
+    let state = state("apply-profile");
+    let profile = state.with_file_name("profile.toml");
+    fs::write(&profile, PROFILE).unwrap();
+    run_ok(&state, &["apply", profile.to_str().unwrap()]);
+    let mouse = EmulatedMouse::load(&state).unwrap();
+
+    let mut expected = EmulatedMouse::new().config_raw();
+    let changes: &[(usize, &[u8])] = &[
+        // Independent axes, 500 Hz.
+        (0x0a, &[0x13]),
+        // Second profile current, 2 profiles enabled.
+        (0x0b, &[0x12, 0xfc]),
+        // X and Y of each profile, the disabled ones keeping their value.
+        (0x0d, &[0x07, 0x07, 0x0f, 0x07, 0x05, 0x05, 0x05, 0x05]),
+        (0x15, &[0x06, 0x06, 0x06, 0x06, 0x07, 0x07, 0x07, 0x07]),
+        (0x1d, &[0xff, 0x00, 0x00, 0x00, 0xff, 0x00]),
+        (0x35, &[0x03]),
+        // Full brightness, fast; 2 colors in RBG order.
+        (0x3c, &[0x43, 0x02, 0xff, 0x00, 0x00, 0x00, 0xff, 0x00]),
+        (0x81, &[0x02]),
+    ];
+    for (offset, octets) in changes {
+        expected[*offset..offset + octets.len()].copy_from_slice(octets);
+    }
+    assert_eq!(hex::encode(mouse.config_raw()), hex::encode(expected));
+
+    let map = mouse.buttonmap_raw().unwrap();
+    let mut expected = vec![0x04, 0x12, 0x00, 0x50, 0x00, 0x00, 0x06, 0x00];
+    expected.extend_from_slice(&[0x11, 0x02, 0x00, 0x00, 0x11, 0x01, 0x00, 0x00]);
+    expected.extend_from_slice(&[0x11, 0x04, 0x00, 0x00, 0x11, 0x08, 0x00, 0x00]);
+    expected.extend_from_slice(&[0x11, 0x10, 0x00, 0x00, 0x70, 0x01, 0x01, 0x01]);
+    for _ in 6..20 {
+        expected.extend_from_slice(&[0x50, 0x01, 0x00, 0x00]);
+    }
+    expected.resize(520, 0);
+    assert_eq!(hex::encode(map), hex::encode(expected));
+
+    let bank = mouse.macro_bank_raw(1).unwrap();
+    let mut expected = vec![0x04, 0x30, 0x02, 0, 0, 0, 0, 0, 0x01, 0x00, 0x02];
+    expected.extend_from_slice(&[0x50, 0x32, 0x04, 0xd0, 0x32, 0x04]);
+    expected.resize(520, 0);
+    assert_eq!(hex::encode(bank), hex::encode(expected));
+    assert_eq!(mouse.macro_bank_raw(0), None);
+}
+
+#[test]
+fn invalid_profile_writes_nothing() {
+    let invalid = [
+        // Above the range of the Model O sensor.
+        "[dpi]\nprofiles = [{ enabled = true, value = 16000, color = 'ff0000' }]",
+        // The config is valid, but the button map is one button too long.
+        "polling_rate = 500\nbuttons = ['disable', 'disable', 'disable', 'disable', \
+         'disable', 'disable', 'disable']",
+        // The config and button map are valid, but the Model O has 4 banks.
+        "lod = 2\nbuttons = ['disable', 'disable', 'disable', 'disable', 'disable', \
+         'disable']\n[[macro]]\nbank = 4\nevents = ['down:keyboard:4:50']",
+        // Events can last at most 4095 ms.
+        "lod = 2\n[[macro]]\nbank = 0\nevents = ['down:keyboard:4:5000']",
+    ];
+    for text in &invalid {
+        let mouse = EmulatedMouse::new();
+        let mut dev = GloriousDevice::new(&mouse);
+        let profile = Profile::from_toml(text).unwrap();
+        assert!(profile.apply(&mut dev).is_err(), "{}", text);
+        assert_eq!(mouse.config_raw(), EmulatedMouse::new().config_raw());
+        assert_eq!(mouse.buttonmap_raw(), None);
+        assert_eq!(mouse.macro_bank_raw(0), None);
+    }
+}
+
+#[test]
+fn too_many_rave_colors_are_rejected() {
+    let state = state("rgb-rave");
+    let out = gloryctl(
+        &state,
+        &[
+            "rgb", "rave", "-c", "ff0000", "00ff00", "-c", "0000ff", "ffffff",
+        ],
+    );
+    assert!(!out.status.success());
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        err.contains("rgb_effect_parameters.rave.colors: 4 colors given, the effect has 2"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn unparsable_profile_is_rejected() {
+    let state = state("apply-unparsable");
+    let profile = state.with_file_name("profile.toml");
+    fs::write(
+        &profile,
+        "[rgb]\neffect = 'breathing'\nbreathing = { speed = 'ludicrous' }\n",
+    )
+    .unwrap();
+    let out = gloryctl(&state, &["apply", profile.to_str().unwrap()]);
+    assert!(!out.status.success());
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(err.contains("Failed to parse"), "{}", err);
+    assert!(!state.exists());
+}
+
+#[test]
+fn invalid_level_in_profile_is_rejected() {
+    let state = state("apply-invalid-level");
+    let profile = state.with_file_name("profile.toml");
+    fs::write(&profile, "[rgb]\nrandom = { speed = 0 }\n").unwrap();
+    let out = gloryctl(&state, &["apply", profile.to_str().unwrap()]);
+    assert!(!out.status.success());
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        err.contains("0 is not a valid level, expected one of slow, medium, fast"),
+        "{}",
+        err
+    );
+    assert!(!state.exists());
+}
+
+#[test]
+fn exported_profile_applies_to_an_identical_config() {
+    let source = state("export-source");
+    let host = source.with_file_name("known.toml");
+    let host_state = host.to_str().unwrap();
+    run_ok(
+        &source,
+        &["dpi", "-d", "1600x800", "-c", "00ffff", "--enable", "2"],
+    );
+    run_ok(
+        &source,
+        &["rgb", "rave", "-s", "fast", "-c", "00ff00", "0000ff"],
+    );
+    run_ok(&source, &["sensor", "-p", "250"]);
+    let args = [
+        "--host-state",
+        host_state,
+        "button",
+        "6:dpi:up",
+        "1:macro:3",
+    ];
+    run_ok(&source, &args);
+    let args = [
+        "--host-state",
+        host_state,
+        "macro",
+        "3",
+        "down:keyboard:4:50",
+    ];
+    run_ok(&source, &args);
+
+    let exported = source.with_file_name("exported.toml");
+    let args = [
+        "--host-state",
+        host_state,
+        "export",
+        exported.to_str().unwrap(),
+    ];
+    run_ok(&source, &args);
+    let text = fs::read_to_string(&exported).unwrap();
+    assert!(
+        text.contains(
+            "buttons = [\"macro:3\", \"mouse:right\", \"mouse:middle\", \"mouse:back\", \
+             \"mouse:forward\", \"dpi:up\"]"
+        ),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("[[macro]]\nbank = 3\nevents = [\"down:keyboard:4:50\"]"),
+        "{}",
+        text
+    );
+
+    // A mouse configured differently takes the same config report.
+    let target = state("export-target");
+    run_ok(&target, &["rgb", "single", "-c", "123456"]);
+    run_ok(&target, &["sensor", "-l", "2"]);
+    run_ok(&target, &["dpi", "-d", "400", "--disable", "3"]);
+    run_ok(&target, &["apply", exported.to_str().unwrap()]);
+    assert_eq!(hex::encode(config(&target)), hex::encode(config(&source)));
+    let target = EmulatedMouse::load(&target).unwrap();
+    let source = EmulatedMouse::load(&source).unwrap();
+    assert_eq!(target.buttonmap_raw(), source.buttonmap_raw());
+    assert_eq!(target.macro_bank_raw(3), source.macro_bank_raw(3));
+}
+
+/// A mouse whose config has the octets gloryctl does not understand set, and
+/// a backup of it.
+fn backed_up_mouse(test: &str) -> (PathBuf, PathBuf, DataReport) {
+    let state = state(test);
+    let mut conf = EmulatedMouse::new().config_raw();
+    for (i, octet) in conf[104..116].iter_mut().enumerate() {
+        *octet = 0xa0 + i as u8;
+    }
+    EmulatedMouse::new()
+        .with_config(&conf)
+        .save(&state)
+        .unwrap();
+    let backup = state.with_file_name("backup.toml");
+    run_ok(&state, &["backup", backup.to_str().unwrap()]);
+    // Start over from a mouse without them and with other settings.
+    fs::remove_file(&state).unwrap();
+    run_ok(&state, &["dpi", "-d", "3200", "1"]);
+    assert_eq!(config(&state)[104..116], [0; 12]);
+    (state, backup, conf)
+}
+
+#[test]
+fn restore_keeps_unknown_octets() {
+    let (state, backup, conf) = backed_up_mouse("restore");
+    run_ok(&state, &["restore", backup.to_str().unwrap()]);
+    assert_eq!(config(&state)[104..116], conf[104..116]);
+    assert_eq!(hex::encode(config(&state)), hex::encode(conf));
+}
+
+#[test]
+fn restore_to_other_firmware_is_refused_unless_forced() {
+    let (state, backup, conf) = backed_up_mouse("restore-firmware");
+    let text = fs::read_to_string(&state).unwrap();
+    fs::write(&state, text.replace("version V103", "version V104")).unwrap();
+    let before = config(&state);
+
+    let out = gloryctl(&state, &["restore", backup.to_str().unwrap()]);
+    assert!(!out.status.success());
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        err.contains("the backup was taken from firmware V103, but the mouse runs V104"),
+        "{}",
+        err
+    );
+    assert_eq!(config(&state), before);
+
+    run_ok(&state, &["restore", "--force", backup.to_str().unwrap()]);
+    assert_eq!(hex::encode(config(&state)), hex::encode(conf));
+}
+
+#[test]
+fn restore_to_other_sensor_is_refused() {
+    let (state, backup, _) = backed_up_mouse("restore-sensor");
+    let mut conf = config(&state);
+    conf[9] = 0x07;
+    EmulatedMouse::new()
+        .with_config(&conf)
+        .save(&state)
+        .unwrap();
+    let out = gloryctl(&state, &["restore", backup.to_str().unwrap()]);
+    assert!(!out.status.success());
+    let err = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        err.contains("sensor 0x06, but this one has 0x07"),
+        "{}",
+        err
+    );
+    assert_eq!(config(&state), conf);
+}
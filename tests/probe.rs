@@ -0,0 +1,91 @@
+//! Probes a recorded mouse which is not in the device database, and reads the
+//! database from a temporary configuration directory.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Run `gloryctl probe` against the recorded session, with the user device
+/// database at `config/gloryctl/devices.toml` containing `database`.
+fn probe(config: &str, database: Option<&str>) -> Output {
+    probe_session("probe.txt", config, database)
+}
+
+fn probe_session(session: &str, config: &str, database: Option<&str>) -> Output {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(config);
+    let path = dir.join("gloryctl").join("devices.toml");
+    let _ = fs::remove_file(&path);
+    if let Some(text) = database {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, text).unwrap();
+    }
+    let session = format!("{}/tests/sessions/{}", env!("CARGO_MANIFEST_DIR"), session);
+    Command::new(env!("CARGO_BIN_EXE_gloryctl"))
+        .env("XDG_CONFIG_HOME", &dir)
+        .arg("--replay")
+        .arg(session)
+        .args(["probe", "258a:1007"])
+        .output()
+        .expect("failed to run gloryctl")
+}
+
+fn stdout(out: &Output) -> String {
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout.clone()).unwrap()
+}
+
+#[test]
+fn unknown_device_gets_a_database_entry() {
+    let out = stdout(&probe("probe-unknown", None));
+    assert!(out.contains("Firmware version: V103\n"), "{}", out);
+    assert!(out.contains("Config: sensor 0x06, 1000 Hz"), "{}", out);
+    assert!(out.contains("product_ids = [0x1007]\n"), "{}", out);
+
+    let entry = &out[out.find("[[model]]").unwrap()..];
+    let out = stdout(&probe("probe-added", Some(entry)));
+    assert!(
+        out.contains("Known as the Unknown mouse 258a:1007."),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn lossy_config_is_listed() {
+    let out = stdout(&probe_session("probe-lossy.txt", "probe-lossy", None));
+    assert!(out.contains("Config: sensor 0x06, 1000 Hz"), "{}", out);
+    assert!(
+        out.contains("0x36 (glorious.bs): read 31, encoded 41\n"),
+        "{}",
+        out
+    );
+    assert!(out.contains("The config decodes, but"), "{}", out);
+    assert!(!out.contains("[[model]]"), "{}", out);
+}
+
+#[test]
+fn invalid_database_is_rejected() {
+    let out = probe(
+        "probe-invalid",
+        Some(
+            "[[model]]\n\
+             name = \"Too many buttons\"\n\
+             vendor_id = 0x258a\n\
+             product_ids = [0x1007]\n\
+             control_interface = 1\n\
+             buttons = 21\n\
+             leds = 6\n\
+             dpi_range = [100, 12000]\n\
+             dpi_step = 100\n\
+             dpi_slots = 8\n\
+             macro_banks = 4\n",
+        ),
+    );
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("buttons must be at most 20"), "{}", stderr);
+}
@@ -0,0 +1,197 @@
+//! Replays recorded sessions against the command line interface. A failure
+//! means the bytes sent to the mouse for the given command have changed.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use gloryctl::Replay;
+
+fn replay(session: &str, args: &[&str]) -> std::process::Output {
+    let path = format!("{}/tests/sessions/{}", env!("CARGO_MANIFEST_DIR"), session);
+    Command::new(env!("CARGO_BIN_EXE_gloryctl"))
+        .arg("--replay")
+        .arg(path)
+        .args(args)
+        .output()
+        .expect("failed to run gloryctl")
+}
+
+fn assert_replays(session: &str, args: &[&str]) {
+    let out = replay(session, args);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+}
+
+#[test]
+fn dpi() {
+    assert_replays("dpi.txt", &["dpi", "-d", "1600", "-c", "00ffff", "2"]);
+}
+
+#[test]
+fn rgb() {
+    assert_replays(
+        "rgb.txt",
+        &[
+            "rgb",
+            "breathing",
+            "-s",
+            "fast",
+            "-c",
+            "ff0000",
+            "-c",
+            "0000ff",
+        ],
+    );
+}
+
+#[test]
+fn buttons() {
+    assert_replays(
+        "button.txt",
+        &[
+            "button",
+            "4:mouse:forward",
+            "5:mouse:back",
+            "6:keyboard:ctrl:6",
+        ],
+    );
+}
+
+#[test]
+fn changed_request_is_detected() {
+    let out = replay("dpi.txt", &["dpi", "-d", "1700", "-c", "00ffff", "2"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Session mismatch"));
+}
+
+#[test]
+fn debounce() {
+    assert_replays("debounce.txt", &["debounce", "6"]);
+}
+
+#[test]
+fn sensor() {
+    assert_replays("sensor.txt", &["sensor", "-p", "500", "-l", "2"]);
+}
+
+#[test]
+fn dpi_insert() {
+    assert_replays(
+        "dpi-insert.txt",
+        &["dpi", "2", "--insert", "-d", "1600x800", "-s"],
+    );
+}
+
+#[test]
+fn invalid_config_is_not_written() {
+    let out = replay("dpi.txt", &["dpi", "-d", "150", "-c", "00ffff", "2"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("dpi_profiles[2].value: 150 DPI"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("Session mismatch"), "{}", stderr);
+}
+
+#[test]
+fn dpi_above_sensor_range_is_not_written() {
+    let out = replay("dpi.txt", &["dpi", "-d", "16000", "-c", "00ffff", "3"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("16000 DPI is not between 100 and 12000"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn slots_beyond_the_model_are_rejected() {
+    let out = replay("dpi.txt", &["dpi", "9"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Model O has 8 profiles"), "{}", stderr);
+
+    let out = replay("button.txt", &["button", "7:mouse:left"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Model O has 6 buttons"), "{}", stderr);
+}
+
+#[test]
+fn verify() {
+    assert_replays("dpi-verify.txt", &["--verify", "dpi", "-d", "1600", "2"]);
+}
+
+#[test]
+fn verify_reports_ignored_write() {
+    let out = replay(
+        "dpi-verify-ignored.txt",
+        &[
+            "--verify",
+            "--verify-retries",
+            "1",
+            "dpi",
+            "-d",
+            "1600",
+            "2",
+        ],
+    );
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("(2 attempts)"), "{}", stderr);
+    assert!(
+        stderr.contains("dpi_profiles[2].value: wrote 1600, read back 600"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn lossy_config_is_not_written() {
+    let out = replay("dpi-lossy.txt", &["dpi", "-d", "1600", "-c", "00ffff", "2"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("0x36 (glorious.bs): read 31, encoded 41"),
+        "{}",
+        stderr
+    );
+}
+
+/// A config which cannot be written can still be read, as it is.
+#[test]
+fn lossy_config_is_dumped() {
+    let out = replay("dump-lossy.txt", &["dump", "--annotate"]);
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("0x0036  31 "), "{}", stdout);
+}
+
+#[test]
+fn dump_annotate() {
+    let out = replay("dump-annotate.txt", &["dump", "--annotate"]);
+    assert!(out.status.success());
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("0x0036  41 "), "{}", stdout);
+    assert!(stdout.contains("glorious.bs\n"), "{}", stdout);
+    assert!(stdout.contains("0x001c  41 00 00 00"), "{}", stdout);
+}
+
+#[test]
+fn session_without_timestamp_is_rejected() {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("no-timestamp.txt");
+    fs::write(&path, "# gloryctl session recording\nsend 6 050201\n").unwrap();
+    let err = Replay::load(&path).err().unwrap().to_string();
+    assert!(
+        err.ends_with("no-timestamp.txt:2: timestamp missing before 'send 6 050201'"),
+        "{}",
+        err
+    );
+}
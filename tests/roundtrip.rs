@@ -0,0 +1,569 @@
+//! Round trips through the encoder and decoder of every report, for generated
+//! values and reports, and the decoding of the example buffer from the README.
+
+use std::convert::TryFrom;
+use std::fs;
+
+use arrayvec::ArrayVec;
+use gloryctl::macros::{Event, EventType, Macro, State};
+use gloryctl::rgb::{self, params, Brightness, Direction, Effect, Speed};
+use gloryctl::{
+    decode, encode, layout, ButtonAction, ButtonMapping, Color, Config, DataReport, DpiProfile,
+    DpiSwitch, DpiValue, Ignored, MacroMode, MediaButton, Model, Modifier, MouseButton,
+    PollingRate, MAX_BUTTONS,
+};
+use proptest::prelude::*;
+
+fn color() -> impl Strategy<Value = Color> {
+    any::<[u8; 3]>().prop_map(|[r, g, b]| Color { r, g, b })
+}
+
+fn colors<A>() -> impl Strategy<Value = ArrayVec<A>>
+where
+    A: arrayvec::Array<Item = Color>,
+{
+    prop::collection::vec(color(), A::CAPACITY).prop_map(|c| c.into_iter().collect())
+}
+
+fn dpi() -> impl Strategy<Value = u16> {
+    (1u16..=256).prop_map(|d| d * 100)
+}
+
+fn speed() -> impl Strategy<Value = Speed> {
+    (0u8..16).prop_map(Speed::from_raw)
+}
+
+fn brightness() -> impl Strategy<Value = Brightness> {
+    (0u8..16).prop_map(Brightness::from_raw)
+}
+
+/// A DPI value which decodes the same way. Both axes having the same value
+/// decodes as a single value.
+fn dpi_value(independent: bool) -> BoxedStrategy<DpiValue> {
+    if independent {
+        prop_oneof![
+            dpi().prop_map(DpiValue::Single),
+            (dpi(), dpi())
+                .prop_filter("same value on both axes", |(x, y)| x != y)
+                .prop_map(|(x, y)| DpiValue::Double(x, y)),
+        ]
+        .boxed()
+    } else {
+        dpi().prop_map(DpiValue::Single).boxed()
+    }
+}
+
+fn dpi_profiles(independent: bool) -> impl Strategy<Value = ArrayVec<[DpiProfile; 8]>> {
+    let profile =
+        (any::<bool>(), dpi_value(independent), color()).prop_map(|(enabled, value, color)| {
+            DpiProfile {
+                enabled,
+                value,
+                color,
+            }
+        });
+    prop::collection::vec(profile, 8).prop_map(|p| p.into_iter().collect())
+}
+
+fn effect_parameters() -> impl Strategy<Value = rgb::EffectParameters> {
+    (
+        (speed(), any::<u8>()),
+        (brightness(), color()),
+        (speed(), any::<u8>(), colors()),
+        (speed(), brightness()),
+        speed(),
+        colors(),
+        (speed(), brightness(), colors()),
+        speed(),
+        (speed(), brightness()),
+        (speed(), color()),
+    )
+        .prop_map(
+            |(
+                glorious,
+                single_color,
+                breathing,
+                tail,
+                seamless,
+                constant,
+                rave,
+                random,
+                wave,
+                single_breathing,
+            )| {
+                rgb::EffectParameters {
+                    glorious: params::Glorious {
+                        speed: glorious.0,
+                        direction: Direction::from_raw(glorious.1),
+                    },
+                    single_color: params::SingleColor {
+                        brightness: single_color.0,
+                        color: single_color.1,
+                    },
+                    breathing: params::Breathing {
+                        speed: breathing.0,
+                        count: breathing.1,
+                        colors: breathing.2,
+                    },
+                    tail: params::Tail {
+                        speed: tail.0,
+                        brightness: tail.1,
+                    },
+                    seamless_breathing: params::SeamlessBreathing { speed: seamless },
+                    constant_rgb: params::ConstantRgb { colors: constant },
+                    rave: params::Rave {
+                        speed: rave.0,
+                        brightness: rave.1,
+                        colors: rave.2,
+                    },
+                    random: params::Random { speed: random },
+                    wave: params::Wave {
+                        speed: wave.0,
+                        brightness: wave.1,
+                    },
+                    single_breathing: params::SingleBreathing {
+                        speed: single_breathing.0,
+                        color: single_breathing.1,
+                    },
+                }
+            },
+        )
+}
+
+/// The parts of the config which the firmware ignores. The second half of the
+/// DPI values is only one of them without independent axes.
+fn ignored(independent: bool) -> impl Strategy<Value = Ignored> {
+    (any::<[u8; 8]>(), any::<[u8; 8]>()).prop_map(move |(n, dpi_values)| Ignored {
+        glorious_brightness: n[0] & 0xf,
+        single_color_speed: n[1] & 0xf,
+        breathing_brightness: n[2] & 0xf,
+        seamless_breathing_brightness: n[3] & 0xf,
+        constant_rgb_brightness: n[4] & 0xf,
+        constant_rgb_speed: n[5] & 0xf,
+        random_brightness: n[6] & 0xf,
+        single_breathing_brightness: n[7] & 0xf,
+        dpi_values: if independent { [0; 8] } else { dpi_values },
+    })
+}
+
+fn config() -> impl Strategy<Value = Config> {
+    any::<bool>().prop_flat_map(|independent| {
+        (
+            any::<[u8; 9]>(),
+            any::<u8>(),
+            (1u8..=4).prop_map(|p| PollingRate::try_from(p).unwrap()),
+            (0u8..16, 0u8..16),
+            dpi_profiles(independent),
+            (0u8..=10).prop_map(|e| Effect::try_from(e).unwrap()),
+            effect_parameters(),
+            (any::<[u8; 12]>(), any::<u8>()),
+            any::<u8>(),
+            ignored(independent),
+        )
+            .prop_map(
+                move |(
+                    header,
+                    sensor_id,
+                    polling_rate,
+                    dpi,
+                    profiles,
+                    effect,
+                    params,
+                    unknown,
+                    lod,
+                    ignored,
+                )| {
+                    Config {
+                        header: ArrayVec::from(header),
+                        sensor_id,
+                        dpi_axes_independent: independent,
+                        polling_rate,
+                        dpi_current_profile: dpi.0,
+                        dpi_profile_count: dpi.1,
+                        dpi_profiles: profiles,
+                        rgb_current_effect: effect,
+                        rgb_effect_parameters: params,
+                        unknown: (ArrayVec::from(unknown.0), unknown.1),
+                        lod,
+                        ignored,
+                    }
+                },
+            )
+    })
+}
+
+fn mouse_button() -> impl Strategy<Value = MouseButton> {
+    any::<u8>().prop_map(MouseButton::from_bits_truncate)
+}
+
+fn modifier() -> impl Strategy<Value = Modifier> {
+    any::<u8>().prop_map(Modifier::from_bits_truncate)
+}
+
+fn button_action() -> impl Strategy<Value = ButtonAction> {
+    prop_oneof![
+        mouse_button().prop_map(ButtonAction::MouseButton),
+        any::<i8>().prop_map(ButtonAction::Scroll),
+        (mouse_button(), any::<u8>(), any::<u8>()).prop_map(|(which, interval, count)| {
+            ButtonAction::RepeatButton {
+                which,
+                interval,
+                count,
+            }
+        }),
+        prop_oneof![
+            Just(DpiSwitch::Cycle),
+            Just(DpiSwitch::Up),
+            Just(DpiSwitch::Down)
+        ]
+        .prop_map(ButtonAction::DpiSwitch),
+        dpi().prop_map(ButtonAction::DpiLock),
+        any::<u32>().prop_map(|b| ButtonAction::MediaButton(MediaButton::from_bits_truncate(b))),
+        (modifier(), any::<u8>())
+            .prop_map(|(modifiers, key)| ButtonAction::KeyboardShortcut { modifiers, key }),
+        Just(ButtonAction::Disabled),
+        (
+            any::<u8>(),
+            prop_oneof![
+                any::<u8>().prop_map(MacroMode::Burst),
+                Just(MacroMode::RepeatUntilRelease),
+                Just(MacroMode::RepeatUntilAnotherPress),
+            ]
+        )
+            .prop_map(|(bank, mode)| ButtonAction::Macro(bank, mode)),
+    ]
+}
+
+fn buttonmap() -> impl Strategy<Value = ButtonMapping> {
+    prop::collection::vec(button_action(), 0..=MAX_BUTTONS)
+        .prop_map(|m| m.into_iter().collect::<ButtonMapping>())
+}
+
+fn event() -> impl Strategy<Value = Event> {
+    (
+        prop_oneof![Just(State::Up), Just(State::Down)],
+        prop_oneof![
+            any::<u8>().prop_map(EventType::Keyboard),
+            modifier().prop_map(EventType::Modifier),
+            mouse_button().prop_map(EventType::Mouse),
+        ],
+        0u16..=0xfff,
+    )
+        .prop_map(|(state, evtype, duration)| Event {
+            state,
+            evtype,
+            duration,
+        })
+}
+
+fn macro_bank() -> impl Strategy<Value = Macro> {
+    (any::<u8>(), prop::collection::vec(event(), 0..=168)).prop_map(|(bank_number, events)| Macro {
+        bank_number,
+        events,
+    })
+}
+
+/// The number of octets of the config report covered by its layout.
+fn config_len() -> usize {
+    layout::config().last().map_or(0, |f| f.offset + f.len)
+}
+
+/// A config report which decodes: the fields which only accept some values
+/// hold one of them, and the others are random.
+fn config_report() -> impl Strategy<Value = DataReport> {
+    prop::collection::vec(any::<u8>(), 520).prop_map(|mut raw| {
+        for f in layout::config() {
+            let b = &mut raw[f.offset..f.offset + f.len];
+            match f.name {
+                // The axes are independent or not, and the polling rate is
+                // one of 4.
+                "dpi_axes_independent/polling_rate" => b[0] = (b[0] & 0x10) | (b[0] % 4 + 1),
+                "rgb_current_effect" => b[0] %= 11,
+                _ => {}
+            }
+        }
+        let mut report = [0; 520];
+        report.copy_from_slice(&raw);
+        report
+    })
+}
+
+/// A macro event in the form written by the encoder.
+fn event_octets() -> impl Strategy<Value = [u8; 3]> {
+    (
+        any::<bool>(),
+        prop_oneof![
+            any::<u8>().prop_map(|k| (5, k)),
+            modifier().prop_map(|m| (6, m.bits())),
+            mouse_button().prop_map(|b| (1, b.bits())),
+        ],
+        0u16..=0xfff,
+    )
+        .prop_map(|(up, (typ, key), duration)| {
+            let d = duration.to_be_bytes();
+            [u8::from(up) << 7 | typ << 4 | d[0], d[1], key]
+        })
+}
+
+fn macro_report() -> impl Strategy<Value = DataReport> {
+    (
+        any::<u8>(),
+        any::<u8>(),
+        prop::collection::vec(event_octets(), 0..=168),
+    )
+        .prop_map(|(bank, unknown, events)| {
+            let mut report = [0; 520];
+            report[..11].copy_from_slice(&[
+                4,
+                0x30,
+                2,
+                0,
+                0,
+                0,
+                0,
+                0,
+                bank,
+                unknown,
+                events.len() as u8,
+            ]);
+            for (i, e) in events.iter().enumerate() {
+                report[11 + 3 * i..14 + 3 * i].copy_from_slice(e);
+            }
+            report
+        })
+}
+
+proptest! {
+    #[test]
+    fn config_round_trip(cfg in config()) {
+        let raw = cfg.to_raw().unwrap();
+        prop_assert_eq!(Config::from_raw(&raw).unwrap(), cfg);
+    }
+
+    #[test]
+    fn config_report_round_trip(raw in config_report()) {
+        let cfg = Config::from_raw(&raw).unwrap();
+        let end = config_len();
+        prop_assert_eq!(&cfg.to_raw().unwrap()[..end], &raw[..end]);
+    }
+
+    #[test]
+    fn buttonmap_round_trip(map in buttonmap()) {
+        let raw = encode::buttonmap(&map).unwrap();
+        prop_assert_eq!(decode::buttonmap(&raw).unwrap(), map);
+        let again = encode::buttonmap(&decode::buttonmap(&raw).unwrap()).unwrap();
+        prop_assert_eq!(&again[..], &raw[..]);
+    }
+
+    #[test]
+    fn macro_round_trip(m in macro_bank()) {
+        let raw = encode::macro_bank(m.bank_number, &m.events).unwrap();
+        prop_assert_eq!(decode::macro_bank(&raw).unwrap(), m);
+    }
+
+    #[test]
+    fn macro_report_round_trip(raw in macro_report()) {
+        let m = decode::macro_report(&raw).unwrap();
+        prop_assert_eq!(&encode::macro_report(&m).unwrap()[..], &raw[..]);
+    }
+
+    #[test]
+    fn event_round_trip(octets in event_octets()) {
+        let event = decode::event(&octets).unwrap();
+        prop_assert_eq!(&encode::macro_bank(0, &[event]).unwrap()[11..14], &octets[..]);
+    }
+}
+
+/// The octets of a report in `tests/reports`, saved as hex.
+fn hex_report(name: &str) -> Vec<u8> {
+    let path = format!("{}/tests/reports/{}", env!("CARGO_MANIFEST_DIR"), name);
+    hex::decode(
+        fs::read_to_string(path)
+            .unwrap()
+            .split_whitespace()
+            .collect::<String>(),
+    )
+    .unwrap()
+}
+
+/// The first config report read in a session of `tests/sessions`.
+fn session_config(name: &str) -> DataReport {
+    let path = format!("{}/tests/sessions/{}", env!("CARGO_MANIFEST_DIR"), name);
+    let text = fs::read_to_string(path).unwrap();
+    let line = text.lines().find(|l| l.contains(" get 4 ")).unwrap();
+    let octets = hex::decode(line.split_whitespace().last().unwrap()).unwrap();
+    let mut report = [0; 520];
+    report[..octets.len()].copy_from_slice(&octets);
+    report
+}
+
+fn readme_config() -> DataReport {
+    let octets = hex_report("config.txt");
+    let mut report = [0; 520];
+    report[..octets.len()].copy_from_slice(&octets);
+    report
+}
+
+#[test]
+fn readme_config_decodes() {
+    let cfg = Config::from_raw(&readme_config()).unwrap();
+    assert_eq!(cfg.sensor_id, 6);
+    assert!(!cfg.dpi_axes_independent);
+    assert_eq!(cfg.polling_rate, PollingRate::Hz1000);
+    assert_eq!((cfg.dpi_current_profile, cfg.dpi_profile_count), (2, 3));
+    let enabled: Vec<_> = cfg.dpi_profiles.iter().map(|p| p.enabled).collect();
+    assert_eq!(
+        enabled,
+        [true, false, true, true, false, false, false, false]
+    );
+    let values: Vec<_> = cfg.dpi_profiles.iter().map(|p| p.value).collect();
+    assert_eq!(
+        values,
+        [500, 600, 600, 600, 700, 700, 800, 800]
+            .iter()
+            .map(|v| DpiValue::Single(*v))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        cfg.dpi_profiles[0].color,
+        Color {
+            r: 0xc0,
+            g: 0,
+            b: 0xc0
+        }
+    );
+    assert_eq!(cfg.rgb_current_effect, Effect::Off);
+    let params = &cfg.rgb_effect_parameters;
+    assert_eq!(params.glorious.speed, Speed::SLOW);
+    assert_eq!(params.glorious.direction, Direction::DOWN);
+    assert_eq!(params.single_color.brightness, Brightness::FULL);
+    assert_eq!(
+        params.single_color.color,
+        Color {
+            r: 0xff,
+            g: 0,
+            b: 0
+        }
+    );
+    assert_eq!(params.breathing.speed, Speed::MEDIUM);
+    assert_eq!(params.breathing.count, 3);
+    assert_eq!(
+        params.breathing.colors[1],
+        Color {
+            r: 0,
+            g: 0,
+            b: 0xff
+        }
+    );
+    assert_eq!(
+        params.breathing.colors[2],
+        Color {
+            r: 0,
+            g: 0xff,
+            b: 0
+        }
+    );
+    assert_eq!(params.tail.brightness, Brightness::FULL);
+    assert_eq!(
+        params.rave.colors[0],
+        Color {
+            r: 0xff,
+            g: 0,
+            b: 0
+        }
+    );
+    assert_eq!(
+        params.rave.colors[1],
+        Color {
+            r: 0,
+            g: 0,
+            b: 0xff
+        }
+    );
+    assert_eq!(params.single_breathing.speed, Speed::MEDIUM);
+    assert_eq!(cfg.lod, 1);
+}
+
+#[test]
+fn readme_config_encodes() {
+    let raw = readme_config();
+    let cfg = Config::from_raw(&raw).unwrap();
+    assert_eq!(&cfg.to_raw().unwrap()[..], &raw[..]);
+}
+
+/// Reports read from a mouse, in which the firmware ignores some parts.
+#[test]
+fn ignored_octets_are_kept() {
+    let raw = session_config("dpi-lossy.txt");
+    let cfg = Config::from_raw(&raw).unwrap();
+    assert_eq!(raw[0x36], 0x31);
+    assert_eq!(&cfg.to_raw().unwrap()[..], &raw[..]);
+    assert_eq!(cfg.official().to_raw().unwrap()[0x36], 0x41);
+
+    let raw = fs::read(format!(
+        "{}/tests/reports/buttonmap.bin",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+    .unwrap();
+    let map = decode::buttonmap_report(&raw).unwrap();
+    assert_eq!(map.header[3], 0x50);
+    assert_eq!(&encode::buttonmap_report(&map).unwrap()[..], &raw[..]);
+
+    let octets = hex_report("macro.txt");
+    let mut raw = [0; 520];
+    raw[..octets.len()].copy_from_slice(&octets);
+    raw[9] = 0x7f;
+    let m = decode::macro_report(&raw).unwrap();
+    assert_eq!(m.unknown, 0x7f);
+    assert_eq!(&encode::macro_report(&m).unwrap()[..], &raw[..]);
+}
+
+#[test]
+fn default_buttonmap_encodes() {
+    let raw = encode::buttonmap(&Model::default().default_buttonmap()).unwrap();
+    assert_eq!(
+        hex::encode(&raw[..32]),
+        concat!(
+            "0412000000000600",
+            "11010000",
+            "11020000",
+            "11040000",
+            "11080000",
+            "11100000",
+            "41000000"
+        )
+    );
+    assert!(raw[32..88].chunks(4).all(|a| a == [0x50, 1, 0, 0]));
+    assert!(raw[88..].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn macro_header_encodes() {
+    let events = [Event {
+        state: State::Down,
+        evtype: EventType::Keyboard(4),
+        duration: 0x123,
+    }];
+    let raw = encode::macro_bank(2, &events).unwrap();
+    assert_eq!(hex::encode(&raw[..14]), "0430020000000000020001512304");
+}
+
+/// The seeds of the fuzz targets are the reports in `tests/reports`.
+#[test]
+fn fuzz_seeds_are_the_example_reports() {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let seed = |target: &str, name: &str| {
+        fs::read(format!("{}/fuzz/seeds/{}/{}", root, target, name)).unwrap()
+    };
+    assert_eq!(seed("config", "readme"), hex_report("config.txt"));
+    assert_eq!(
+        seed("buttonmap", "default"),
+        fs::read(format!("{}/tests/reports/buttonmap.bin", root)).unwrap()
+    );
+    let macro_bank = hex_report("macro.txt");
+    assert_eq!(seed("macro_bank", "readme"), macro_bank);
+    assert_eq!(seed("event", "readme"), &macro_bank[11..]);
+    for event in macro_bank[11..].chunks(3) {
+        decode::event(event).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+//! The checks run on a config before it is written to the mouse.
+
+use gloryctl::rgb::{Brightness, Direction, Effect, Speed};
+use gloryctl::{
+    validate_buttonmap, ButtonAction, Config, DpiValue, EmulatedMouse, Error, Model, MODELS,
+};
+
+fn default_config() -> Config {
+    Config::from_raw(&EmulatedMouse::new().config_raw()).unwrap()
+}
+
+/// The problems found in `conf` for a Model O, as "path: reason" lines.
+fn problems(conf: &Config) -> Vec<String> {
+    problems_for(conf, &MODELS[0])
+}
+
+fn problems_for(conf: &Config, model: &Model) -> Vec<String> {
+    match conf.validate(model) {
+        Ok(()) => Vec::new(),
+        Err(Error::Invalid(problems)) => problems.iter().map(|p| p.to_string()).collect(),
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[test]
+fn default_config_is_valid() {
+    assert_eq!(problems(&default_config()), Vec::<String>::new());
+}
+
+#[test]
+fn default_levels_are_valid() {
+    assert!(Speed::default().is_valid());
+    assert!(Brightness::default().is_valid());
+    assert!(Direction::default().is_valid());
+}
+
+#[test]
+fn breathing_count_is_only_checked_for_breathing() {
+    let mut conf = default_config();
+    conf.rgb_effect_parameters.breathing.count = 0;
+    conf.rgb_current_effect = Effect::Glorious;
+    assert_eq!(problems(&conf), Vec::<String>::new());
+
+    conf.rgb_current_effect = Effect::Breathing;
+    assert_eq!(
+        problems(&conf),
+        ["rgb_effect_parameters.breathing.count: must be between 1 and 7"]
+    );
+}
+
+#[test]
+fn current_profile_is_counted_from_1() {
+    let mut conf = default_config();
+    conf.dpi_current_profile = 1;
+    conf.dpi_profiles[1].enabled = false;
+    assert_eq!(
+        problems(&conf),
+        ["dpi_current_profile: profile 2 is disabled"]
+    );
+}
+
+#[test]
+fn only_used_dpi_slots_are_checked_against_the_sensor() {
+    let mut conf = default_config();
+    conf.dpi_profiles[1].enabled = false;
+    conf.dpi_profiles[1].value = DpiValue::Single(16000);
+    assert_eq!(problems(&conf), Vec::<String>::new());
+
+    // Unused slots still have to fit in the report.
+    conf.dpi_profiles[1].value = DpiValue::Single(16050);
+    assert_eq!(
+        problems(&conf),
+        ["dpi_profiles[2].value: 16050 DPI is not a multiple of 100"]
+    );
+
+    conf.dpi_profiles[1].enabled = true;
+    conf.dpi_profiles[1].value = DpiValue::Single(16000);
+    assert_eq!(
+        problems(&conf),
+        ["dpi_profiles[2].value: 16000 DPI is not between 100 and 12000"]
+    );
+
+    let four_slots = Model {
+        dpi_slots: 4,
+        ..MODELS[0].clone()
+    };
+    conf.dpi_profiles[1].enabled = false;
+    conf.dpi_profiles[5].enabled = true;
+    conf.dpi_profiles[5].value = DpiValue::Single(16000);
+    assert_eq!(
+        problems_for(&conf, &four_slots),
+        ["dpi_profiles[6].enabled: the Glorious Model O only has 4 slots"]
+    );
+}
+
+#[test]
+fn buttons_are_counted_from_1() {
+    let model = &MODELS[0];
+    let mut map = model.default_buttonmap();
+    map[2] = ButtonAction::Scroll(0);
+    match validate_buttonmap(&map, model) {
+        Err(Error::Invalid(problems)) => assert_eq!(
+            problems.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            ["buttons[3]: scroll amount 0 is not between -127 and 127, or is 0"]
+        ),
+        r => panic!("{:?}", r),
+    }
+}